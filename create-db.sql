@@ -4,19 +4,15 @@ CREATE DATABASE eventstorerust;
 
 CREATE EXTENSION IF NOT EXISTS "uuid-ossp";
 
-DROP TABLE events;
+-- Tables are created and kept up to date by the schema migrations `PgStoreAdapter::new` and
+-- `PgCacheAdapter::new` apply. Dropping them here, along with the record of applied migrations,
+-- resets the database to an empty schema.
+DROP TABLE IF EXISTS events_outbox;
 
-CREATE TABLE events (
-    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
-    data jsonb NOT NULL,
-    context jsonb DEFAULT '{}'::jsonb
-);
+DROP TABLE IF EXISTS events;
 
-DROP TABLE aggregate_cache;
+DROP TABLE IF EXISTS aggregate_cache;
 
-CREATE TABLE aggregate_cache (
-    id VARCHAR(64) PRIMARY KEY,
-    -- id character varying(64) PRIMARY KEY,
-    data jsonb NOT NULL,
-    time timestamp without time zone DEFAULT now()
-);
+DROP TABLE IF EXISTS aggregate_snapshots;
+
+DROP TABLE IF EXISTS schema_migrations;
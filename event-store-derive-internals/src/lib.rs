@@ -1,6 +1,8 @@
 extern crate serde;
+pub extern crate serde_json;
 
 use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value as JsonValue;
 
 /// Trait to be implemented by all domain events
 pub trait EventData: Serialize + DeserializeOwned {
@@ -9,7 +11,72 @@ pub trait EventData: Serialize + DeserializeOwned {
     fn event_namespace() -> &'static str;
 
     fn event_type() -> &'static str;
+
+    /// Schema version of this event, written to the `event_version` field when serialized
+    ///
+    /// Events without an `#[event_store(version = N)]` attribute, and payloads saved without an
+    /// `event_version` field, are version 1.
+    fn event_version() -> u32 {
+        1
+    }
 }
 
 /// Trait implemented on the events enum
-pub trait Events: Serialize + DeserializeOwned {}
+pub trait Events: Serialize + DeserializeOwned {
+    /// The store position of the event held in this variant, if it was read from a store
+    fn position(&self) -> Option<i64>;
+
+    /// The namespace and type of each event this enum can hold, in variant order
+    fn event_namespaces_and_types() -> Vec<(&'static str, &'static str)>;
+}
+
+/// Converts a serialized event payload from one schema version to the next
+///
+/// Register upcasters on an event with `#[event_store(upcasters = "NAME")]`, where `NAME` is a
+/// constant or static `&[Upcaster]` in scope. When an older payload is deserialized, upcasters are
+/// applied one version at a time until the payload reaches the event's current version.
+#[derive(Clone, Copy)]
+pub struct Upcaster {
+    /// Version of the payloads this upcaster accepts. It must return a version `from_version + 1`
+    /// payload.
+    pub from_version: u32,
+
+    /// Transform the payload, which includes the `event_namespace`, `event_type` and
+    /// `event_version` fields
+    pub upcast: fn(JsonValue) -> Result<JsonValue, String>,
+}
+
+/// Bring a serialized event payload up to `current_version` using `upcasters`
+///
+/// The payload's version is read from its `event_version` field, defaulting to 1 if it's missing.
+/// The returned payload has its `event_version` set to `current_version`.
+pub fn upcast(
+    mut payload: JsonValue,
+    current_version: u32,
+    upcasters: &[Upcaster],
+) -> Result<JsonValue, String> {
+    let mut version = payload["event_version"].as_u64().unwrap_or(1) as u32;
+
+    if version > current_version {
+        return Err(format!(
+            "Event version {} is newer than the latest known version {}",
+            version, current_version
+        ));
+    }
+
+    while version < current_version {
+        let upcaster = upcasters
+            .iter()
+            .find(|upcaster| upcaster.from_version == version)
+            .ok_or_else(|| format!("No upcaster registered for event version {}", version))?;
+
+        payload = (upcaster.upcast)(payload)?;
+        version += 1;
+    }
+
+    if let Some(fields) = payload.as_object_mut() {
+        fields.insert("event_version".into(), current_version.into());
+    }
+
+    Ok(payload)
+}
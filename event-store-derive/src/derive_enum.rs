@@ -1,5 +1,5 @@
-use crate::ns::get_enum_struct_names;
 use crate::ns::EnumInfo;
+use crate::ns::{get_enum_event_data_types, get_enum_struct_names};
 use proc_macro2::{Ident, Span, TokenStream};
 use std::iter::repeat;
 use syn::{DataEnum, DeriveInput};
@@ -101,6 +101,12 @@ pub fn derive_enum(parsed: &DeriveInput, enum_body: &DataEnum) -> TokenStream {
 
     let (impl_generics, ty_generics, _where_clause) = info.generics.split_for_impl();
 
+    let item_idents = repeat(item_ident);
+    let variant_idents = info.variant_idents.iter();
+    let event_data_types = get_enum_event_data_types(&info.enum_body);
+    let data_types = event_data_types.iter();
+    let data_types2 = event_data_types.iter();
+
     quote! {
         #[allow(non_upper_case_globals, unused_attributes, unused_imports)]
         const #dummy_const: () = {
@@ -111,7 +117,22 @@ pub fn derive_enum(parsed: &DeriveInput, enum_body: &DataEnum) -> TokenStream {
             use serde::de::{Deserialize, Deserializer, IntoDeserializer};
             use serde::ser::{Serialize, Serializer, SerializeMap};
 
-            impl #impl_generics event_store_derive_internals::Events for #item_ident #ty_generics {}
+            impl #impl_generics event_store_derive_internals::Events for #item_ident #ty_generics {
+                fn position(&self) -> Option<i64> {
+                    match self {
+                        #(#item_idents::#variant_idents(evt) => evt.position,)*
+                    }
+                }
+
+                fn event_namespaces_and_types() -> Vec<(&'static str, &'static str)> {
+                    vec![
+                        #((
+                            <#data_types as event_store_derive_internals::EventData>::event_namespace(),
+                            <#data_types2 as event_store_derive_internals::EventData>::event_type(),
+                        ),)*
+                    ]
+                }
+            }
 
             #ser
             #de
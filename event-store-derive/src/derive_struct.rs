@@ -11,6 +11,7 @@ fn impl_serialize(info: &StructInfo) -> TokenStream {
         ref renamed_item_ident_quoted,
         ref struct_body,
         ref struct_namespace_quoted,
+        ref version,
         ..
     } = info;
 
@@ -22,6 +23,15 @@ fn impl_serialize(info: &StructInfo) -> TokenStream {
         panic!("Unnamed and unit structs are not supported");
     };
 
+    // Unversioned events are serialized without a version field, as they were before versioning
+    let (version_field, version_value) = match version {
+        Some(version) => (
+            quote! { event_version: u32, },
+            quote! { event_version: #version, },
+        ),
+        None => (quote! {}, quote! {}),
+    };
+
     quote! {
         impl Serialize for #item_ident {
             fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -32,12 +42,14 @@ fn impl_serialize(info: &StructInfo) -> TokenStream {
                 struct Helper<'a> {
                     event_type: &'a str,
                     event_namespace: &'a str,
+                    #version_field
                     #body
                 }
 
                 let out = Helper {
                     event_namespace: #struct_namespace_quoted,
                     event_type: #renamed_item_ident_quoted,
+                    #version_value
                     #(#field_idents: self.#field_idents2.clone(), )*
                 };
 
@@ -54,6 +66,8 @@ fn impl_deserialize(info: &StructInfo) -> TokenStream {
         ref renamed_item_ident_quoted,
         ref struct_body,
         ref struct_namespace_quoted,
+        ref version,
+        ref upcasters,
         ..
     } = info;
 
@@ -65,6 +79,29 @@ fn impl_deserialize(info: &StructInfo) -> TokenStream {
         panic!("Unnamed and unit structs are not supported");
     };
 
+    // Versioned events are read as JSON first so older payloads can be upcast to the current shape
+    let read_helper = match version {
+        Some(version) => {
+            let upcasters = match upcasters {
+                Some(upcasters) => quote! { #upcasters },
+                None => quote! { &[] },
+            };
+
+            quote! {
+                let payload = event_store_derive_internals::serde_json::Value::deserialize(deserializer)
+                    .map_err(de::Error::custom)?;
+
+                let payload = event_store_derive_internals::upcast(payload, #version, #upcasters)
+                    .map_err(de::Error::custom)?;
+
+                let helper = Helper::deserialize(payload).map_err(de::Error::custom)?;
+            }
+        }
+        None => quote! {
+            let helper = Helper::deserialize(deserializer).map_err(de::Error::custom)?;
+        },
+    };
+
     quote! {
         impl<'de> Deserialize<'de> for #item_ident {
             fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -80,7 +117,7 @@ fn impl_deserialize(info: &StructInfo) -> TokenStream {
                     #body
                 }
 
-                let helper = Helper::deserialize(deserializer).map_err(de::Error::custom)?;
+                #read_helper
 
                 if helper.event_namespace != #struct_namespace_quoted {
                     Err(de::Error::custom(format!("Incorrect event namespace {}, expected {}", helper.event_namespace, #struct_namespace_quoted)))
@@ -104,9 +141,16 @@ pub fn derive_struct(parsed: &DeriveInput, struct_body: &DataStruct) -> TokenStr
         ref item_ident,
         ref renamed_namespace_and_type,
         ref renamed_item_ident_quoted,
+        ref version,
         ..
     } = &info;
 
+    let event_version = version.map(|version| {
+        quote! {
+            fn event_version() -> u32 { #version }
+        }
+    });
+
     let ser = impl_serialize(&info);
     let de = impl_deserialize(&info);
 
@@ -130,6 +174,7 @@ pub fn derive_struct(parsed: &DeriveInput, struct_body: &DataStruct) -> TokenStr
                 fn event_namespace_and_type() -> &'static str { #renamed_namespace_and_type }
                 fn event_namespace() -> &'static str { #struct_namespace_quoted }
                 fn event_type() -> &'static str { #renamed_item_ident_quoted }
+                #event_version
             }
 
             #ser
@@ -5,7 +5,10 @@ use proc_macro2::{Ident, Span, TokenStream, TokenTree};
 use quote::ToTokens;
 use quote::__rt::TokenTree::Group;
 use std::string::ToString;
-use syn::{Attribute, Data, DataEnum, DataStruct, DeriveInput, Fields, FieldsNamed, Generics};
+use syn::{
+    Attribute, Data, DataEnum, DataStruct, DeriveInput, Fields, FieldsNamed, GenericArgument,
+    Generics, PathArguments, Type, TypePath,
+};
 
 pub struct EnumInfo {
     pub item_ident: TokenStream,
@@ -57,6 +60,8 @@ pub struct StructInfo {
     pub struct_body: DataStruct,
     pub struct_namespace: Ident,
     pub struct_namespace_quoted: String,
+    pub version: Option<u32>,
+    pub upcasters: Option<Ident>,
     pub generics: Generics,
 }
 
@@ -67,6 +72,18 @@ impl StructInfo {
 
         let struct_rename = get_attribute_ident(&parsed.attrs, "rename");
 
+        let version = get_attribute_value(&parsed.attrs, "version").map(|version| {
+            version
+                .parse::<u32>()
+                .expect("Version attribute must be a positive integer")
+        });
+
+        let upcasters = get_attribute_ident(&parsed.attrs, "upcasters");
+
+        if upcasters.is_some() && version.is_none() {
+            panic!("Upcasters can only be registered on events with a version attribute");
+        }
+
         let item_ident = parsed.clone().ident;
         let renamed_item_ident = struct_rename.unwrap_or(item_ident.clone());
 
@@ -100,12 +117,20 @@ impl StructInfo {
             struct_body: struct_body.clone(),
             struct_namespace,
             struct_namespace_quoted,
+            version,
+            upcasters,
             generics: parsed.generics.clone(),
         }
     }
 }
 
 pub fn get_attribute_ident(input: &Vec<Attribute>, attribute_name: &'static str) -> Option<Ident> {
+    get_attribute_value(input, attribute_name).map(|value| Ident::new(&value, Span::call_site()))
+}
+
+/// Get the value of an attribute like `version = 2` or `namespace = "something"`, with any quotes
+/// removed
+pub fn get_attribute_value(input: &Vec<Attribute>, attribute_name: &'static str) -> Option<String> {
     let ident_match = Ident::new(attribute_name, Span::call_site());
 
     input
@@ -131,10 +156,9 @@ pub fn get_attribute_ident(input: &Vec<Attribute>, attribute_name: &'static str)
                             (
                                 Some(TokenTree::Ident(ref ident)),
                                 Some(TokenTree::Literal(ref attribute_value)),
-                            ) if *ident == ident_match => Some(Ident::new(
-                                attribute_value.to_string().trim_matches('"').into(),
-                                Span::call_site(),
-                            )),
+                            ) if *ident == ident_match => {
+                                Some(attribute_value.to_string().trim_matches('"').into())
+                            }
                             _ => None,
                         }
                     }
@@ -159,6 +183,41 @@ pub fn get_enum_struct_names(enum_body: &DataEnum) -> Vec<TokenStream> {
         .collect::<Vec<TokenStream>>()
 }
 
+/// Get the event data type held by each variant, e.g. `TestEvent` for `Inc(Event<TestEvent>)`
+pub fn get_enum_event_data_types(enum_body: &DataEnum) -> Vec<TokenStream> {
+    enum_body
+        .variants
+        .iter()
+        .map(|variant| {
+            let ty = variant
+                .fields
+                .iter()
+                .next()
+                .map(|field| field.ty.clone())
+                .expect("Expected struct type");
+
+            match ty {
+                Type::Path(TypePath { path, .. }) => {
+                    path.segments
+                        .iter()
+                        .last()
+                        .and_then(|segment| match &segment.arguments {
+                            PathArguments::AngleBracketed(args) => {
+                                args.args.iter().find_map(|arg| match arg {
+                                    GenericArgument::Type(ty) => Some(ty.into_token_stream()),
+                                    _ => None,
+                                })
+                            }
+                            _ => None,
+                        })
+                }
+                _ => None,
+            }
+            .expect("Expected variant to hold an Event<T>")
+        })
+        .collect::<Vec<TokenStream>>()
+}
+
 pub fn expand_derive_namespace(parsed: &DeriveInput) -> TokenStream {
     match parsed.data {
         Data::Enum(ref body) => derive_enum(&parsed, &body),
@@ -0,0 +1,100 @@
+use super::{CacheAdapter, CacheResult, SnapshotKey};
+use crate::adapters::AdapterFuture;
+use crate::error::Error;
+use log::{debug, trace};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{from_value, to_value, Value as JsonValue};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
+
+/// In-memory cache adapter
+///
+/// Clones of an adapter share the same cache. Intended for use in tests.
+#[derive(Clone, Default)]
+pub struct MemoryCacheAdapter {
+    items: Arc<RwLock<HashMap<String, (JsonValue, i64)>>>,
+}
+
+impl MemoryCacheAdapter {
+    /// Create a new, empty in-memory cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn poisoned<T>(_: T) -> Error {
+    Error::Connection("Memory cache lock poisoned".into())
+}
+
+impl CacheAdapter for MemoryCacheAdapter {
+    fn read<'a, T>(&'a self, key: &'a str) -> AdapterFuture<'a, Option<CacheResult<T>>>
+    where
+        T: DeserializeOwned + Debug + Send + 'a,
+    {
+        Box::pin(async move {
+            trace!("Cache read key {}", key);
+
+            let items = self.items.read().map_err(poisoned)?;
+
+            let res = items
+                .get(key)
+                .map(|(data, position)| {
+                    from_value(data.clone()).map(|decoded: T| (decoded, *position))
+                })
+                .transpose()?;
+
+            trace!("Cache read result {:?}", res);
+
+            Ok(res)
+        })
+    }
+
+    fn save<'a, V>(&'a self, key: &'a str, value: &'a V, position: i64) -> AdapterFuture<'a, ()>
+    where
+        V: Serialize + Debug,
+    {
+        debug!("Cache aggregate result under key {}: {:?}", key, value);
+
+        let value = to_value(value);
+
+        Box::pin(async move {
+            self.items
+                .write()
+                .map_err(poisoned)?
+                .insert(key.into(), (value?, position));
+
+            Ok(())
+        })
+    }
+
+    fn save_snapshot<'a, V>(
+        &'a self,
+        key: SnapshotKey<'a>,
+        value: &'a V,
+        position: i64,
+    ) -> AdapterFuture<'a, ()>
+    where
+        V: Serialize + Debug,
+    {
+        debug!("Snapshot {:?} at position {}: {:?}", key, position, value);
+
+        let cache_key = key.cache_key();
+        let value = to_value(value);
+
+        Box::pin(async move {
+            let mut items = self.items.write().map_err(poisoned)?;
+
+            // Never replace a snapshot with one covering fewer events
+            match items.get(&cache_key) {
+                Some((_, existing_position)) if *existing_position > position => {}
+                _ => {
+                    items.insert(cache_key, (value?, position));
+                }
+            }
+
+            Ok(())
+        })
+    }
+}
@@ -1,11 +1,121 @@
-use chrono::prelude::*;
+use crate::adapters::AdapterFuture;
+use log::debug;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{to_value, Value as JsonValue};
+use std::fmt::Debug;
 
+mod memory;
 mod pg;
 mod redis;
+mod sqlite;
 
 // TODO: Rename this. `Result` implies an error condition, but it's not. Maybe `CacheItem`? Idk.
-/// Result of a cache search
-pub type CacheResult<T> = (T, DateTime<Utc>);
+/// Result of a cache search: the cached item and the position of the last event applied to it
+pub type CacheResult<T> = (T, i64);
 
-pub use self::pg::PgCacheAdapter;
+pub use self::memory::MemoryCacheAdapter;
+pub use self::pg::{PgCacheAdapter, PgCacheTables};
 pub use self::redis::RedisCacheAdapter;
+pub use self::sqlite::SqliteCacheAdapter;
+
+/// A key/value cache used to memoize aggregation results
+///
+/// Implementations must return the event position saved alongside the item so that aggregation
+/// can continue from the event after it.
+pub trait CacheAdapter: Clone + Send + Sync + 'static {
+    /// Read an item from the cache by key, parsing to type `T`
+    ///
+    /// A missing item, or one saved without a position, resolves to `Ok(None)`
+    fn read<'a, T>(&'a self, key: &'a str) -> AdapterFuture<'a, Option<CacheResult<T>>>
+    where
+        T: DeserializeOwned + Debug + Send + 'a;
+
+    /// Save an item into the cache, replacing any existing item under the same key
+    ///
+    /// `position` is the position of the last event applied to `value`.
+    fn save<'a, V>(&'a self, key: &'a str, value: &'a V, position: i64) -> AdapterFuture<'a, ()>
+    where
+        V: Serialize + Debug;
+
+    /// Read the latest snapshot saved under `key`
+    ///
+    /// Snapshots saved with any other aggregate type, stream, query or version are ignored. By
+    /// default snapshots are read from the cache under a key made from all parts of `key`.
+    fn read_snapshot<'a, T>(
+        &'a self,
+        key: SnapshotKey<'a>,
+    ) -> AdapterFuture<'a, Option<CacheResult<T>>>
+    where
+        T: DeserializeOwned + Debug + Send + 'a,
+    {
+        let cache_key = key.cache_key();
+
+        Box::pin(async move { await!(self.read(&cache_key)) })
+    }
+
+    /// Save a snapshot of an aggregate over a stream under `key`
+    ///
+    /// `position` is the position of the last event applied to `value`. Implementations must never
+    /// replace a snapshot with one at an earlier position, so a slow writer can't roll a snapshot
+    /// back. The default implementation reads the existing snapshot from the cache first, so it's
+    /// only safe against concurrent writers if the adapter overrides it.
+    fn save_snapshot<'a, V>(
+        &'a self,
+        key: SnapshotKey<'a>,
+        value: &'a V,
+        position: i64,
+    ) -> AdapterFuture<'a, ()>
+    where
+        V: Serialize + Debug,
+    {
+        let cache_key = key.cache_key();
+        let value = to_value(value);
+
+        Box::pin(async move {
+            let value = value?;
+            let existing = await!(self.read::<JsonValue>(&cache_key))?;
+
+            match existing {
+                Some((_, existing_position)) if existing_position > position => {
+                    debug!(
+                        "Keep snapshot {} at position {}, newer than {}",
+                        cache_key, existing_position, position
+                    );
+
+                    Ok(())
+                }
+                _ => await!(self.save(&cache_key, &value, position)),
+            }
+        })
+    }
+}
+
+/// Identifies the snapshots of one aggregate type over one stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnapshotKey<'a> {
+    /// The aggregate type, from [`crate::SnapshotPolicy::aggregate_type`]
+    pub aggregate_type: &'a str,
+
+    /// ID of the stream the aggregate is folded over
+    pub stream_id: &'a str,
+
+    /// Unique ID of the query the aggregate is folded over, from [`crate::StoreQuery::unique_id`]
+    ///
+    /// Different queries over the same stream can match different events, so they don't share
+    /// snapshots.
+    pub query_id: &'a str,
+
+    /// Schema version of the aggregate type, from [`crate::SnapshotPolicy::version`]
+    pub version: u32,
+}
+
+impl<'a> SnapshotKey<'a> {
+    /// Cache key for snapshots saved by the default [`CacheAdapter::save_snapshot`]
+    pub(crate) fn cache_key(&self) -> String {
+        format!(
+            "snapshot:{}:{}:{}:{}",
+            self.aggregate_type, self.version, self.stream_id, self.query_id
+        )
+    }
+}
@@ -1,5 +1,7 @@
-use super::CacheResult;
-use chrono::prelude::*;
+use super::{CacheAdapter, CacheResult, SnapshotKey};
+use crate::adapters::migrations::{migrate, qualify, quote_ident, Migration};
+use crate::adapters::AdapterFuture;
+use crate::error::Error;
 use log::{debug, trace};
 use r2d2::Pool;
 use r2d2_postgres::PostgresConnectionManager;
@@ -8,96 +10,331 @@ use serde::Serialize;
 use serde_json::from_value;
 use serde_json::to_value;
 use std::fmt::Debug;
-use std::io;
 
-const INIT_QUERIES: &'static str = r#"
--- Create UUID extension just in case
-create extension if not exists "uuid-ossp";
+/// Schema changes for the cache table, applied in order by [`PgCacheAdapter::new`]
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Create aggregate cache table",
+        sql: r#"
+            -- Create UUID extension just in case
+            create extension if not exists "uuid-ossp";
 
--- Create cache table if it doesn't already exist
-create table if not exists aggregate_cache(
-    id varchar(64) not null,
-    data jsonb not null,
-    time timestamp with time zone,
-    primary key(id)
-);
+            create table if not exists {cache}(
+                id varchar(64) not null,
+                data jsonb not null,
+                time timestamp with time zone,
+                primary key(id)
+            );
 
-create index if not exists cache_time on aggregate_cache (time desc);
-"#;
+            create index if not exists {time_index} on {cache} (time desc);
+        "#,
+    },
+    Migration {
+        version: 2,
+        description: "Add position of last applied event",
+        sql: r#"
+            alter table {cache} add column if not exists position bigint;
+        "#,
+    },
+    Migration {
+        version: 3,
+        description: "Store cache times with time zone",
+        sql: r#"
+            -- Tables created by older versions of create-db.sql have no time zone
+            alter table {cache} alter column time type timestamp with time zone;
+        "#,
+    },
+    Migration {
+        version: 4,
+        description: "Create aggregate snapshots table",
+        sql: r#"
+            create table if not exists {snapshots}(
+                aggregate_type varchar(255) not null,
+                stream_id varchar(255) not null,
+                query_id varchar(64) not null,
+                version integer not null,
+                position bigint not null,
+                data jsonb not null,
+                time timestamp with time zone not null default now(),
+                primary key(aggregate_type, stream_id, query_id, version)
+            );
+        "#,
+    },
+];
+
+/// Names of the Postgres schema and tables a [`PgCacheAdapter`] uses
+///
+/// By default the tables are created in the connection's current schema and named
+/// `aggregate_cache` and `aggregate_snapshots`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgCacheTables {
+    schema: Option<String>,
+    cache: String,
+    snapshots: String,
+}
+
+impl Default for PgCacheTables {
+    fn default() -> Self {
+        Self {
+            schema: None,
+            cache: "aggregate_cache".into(),
+            snapshots: "aggregate_snapshots".into(),
+        }
+    }
+}
+
+impl PgCacheTables {
+    /// Use the default table names in the connection's current schema
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create the tables in the given schema, creating the schema if it doesn't exist
+    pub fn with_schema(self, schema: &str) -> Self {
+        Self {
+            schema: Some(schema.into()),
+            ..self
+        }
+    }
+
+    /// Set the name of the cache table
+    pub fn with_cache_table(self, cache: &str) -> Self {
+        Self {
+            cache: cache.into(),
+            ..self
+        }
+    }
+
+    /// Set the name of the aggregate snapshots table
+    pub fn with_snapshots_table(self, snapshots: &str) -> Self {
+        Self {
+            snapshots: snapshots.into(),
+            ..self
+        }
+    }
+
+    /// The quoted, schema-qualified name of the cache table
+    fn cache(&self) -> String {
+        qualify(self.schema.as_ref().map(|s| s.as_str()), &self.cache)
+    }
+
+    /// The quoted, schema-qualified name of the snapshots table
+    fn snapshots(&self) -> String {
+        qualify(self.schema.as_ref().map(|s| s.as_str()), &self.snapshots)
+    }
+
+    fn migration_vars(&self) -> Vec<(&'static str, String)> {
+        // The index created before table names were configurable keeps its original name
+        let time_index = if self.cache == "aggregate_cache" {
+            String::from("cache_time")
+        } else {
+            format!("{}_time", self.cache)
+        };
+
+        vec![
+            ("cache", self.cache()),
+            ("snapshots", self.snapshots()),
+            ("time_index", quote_ident(&time_index)),
+        ]
+    }
+}
 
 /// Postgres-backed cache adapter
 #[derive(Clone)]
 pub struct PgCacheAdapter {
     conn: Pool<PostgresConnectionManager>,
+    cache: String,
+    snapshots: String,
 }
 
 impl PgCacheAdapter {
     /// Create a new PG-backed cache adapter instance
     ///
-    /// This will attempt to create the cache table if it does not already exist
-    pub async fn new(conn: Pool<PostgresConnectionManager>) -> Result<Self, io::Error> {
-        conn.get()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?
-            .batch_execute(INIT_QUERIES)?;
+    /// This applies any schema migrations for the cache table that haven't been applied yet
+    pub async fn new(conn: Pool<PostgresConnectionManager>) -> Result<Self, Error> {
+        await!(Self::new_with_tables(conn, PgCacheTables::default()))
+    }
+
+    /// Create a new PG-backed cache adapter using the given schema and table names
+    ///
+    /// Migrations are applied to, and recorded in, the configured schema.
+    pub async fn new_with_tables(
+        conn: Pool<PostgresConnectionManager>,
+        tables: PgCacheTables,
+    ) -> Result<Self, Error> {
+        migrate(
+            &*conn.get()?,
+            tables.schema.as_ref().map(|s| s.as_str()),
+            &tables.cache,
+            MIGRATIONS,
+            &tables.migration_vars(),
+        )?;
 
-        Ok(Self { conn })
+        Ok(Self {
+            conn,
+            cache: tables.cache(),
+            snapshots: tables.snapshots(),
+        })
     }
+}
 
-    /// Read an item from the cache by key, parsing to type `T`
-    pub async fn read<'a, T>(&'a self, key: &'a str) -> Result<Option<CacheResult<T>>, io::Error>
+impl CacheAdapter for PgCacheAdapter {
+    fn read<'a, T>(&'a self, key: &'a str) -> AdapterFuture<'a, Option<CacheResult<T>>>
     where
-        T: DeserializeOwned + Debug,
+        T: DeserializeOwned + Debug + Send + 'a,
     {
-        trace!("Cache read key {}", key);
+        Box::pin(async move {
+            trace!("Cache read key {}", key);
 
-        self.conn
-            .get()
-            .unwrap()
-            .query(
-                "select data, time from aggregate_cache where id = $1 limit 1",
+            let rows = self.conn.get()?.query(
+                &format!(
+                    "select data, position from {} where id = $1 limit 1",
+                    self.cache
+                ),
                 &[&key],
-            )
-            .map(|rows| {
-                // `rows.get()` panics if index is out of bounds, hence this check
-                let res = if rows.len() != 1 {
-                    None
-                } else {
-                    let row = rows.get(0);
-                    let utc: DateTime<Utc> = row.get(1);
-
-                    Some((
-                        from_value(row.get(0))
-                            .map(|decoded: T| decoded)
-                            .expect("Cant decode the cached entity"),
-                        utc,
-                    ))
-                };
+            )?;
+
+            // `rows.get()` panics if index is out of bounds, hence this check
+            let res = if rows.len() != 1 {
+                None
+            } else {
+                let row = rows.get(0);
+
+                // Items cached before positions were recorded have no position and are rebuilt
+                let position: Option<i64> = row.get(1);
 
-                trace!("Cache read result {:?}", res);
+                match position {
+                    Some(position) => {
+                        let decoded: T = from_value(row.get(0)).map_err(|e| {
+                            Error::Serialization(format!(
+                                "Could not decode cached entity {}: {}",
+                                key, e
+                            ))
+                        })?;
 
-                res
-            })
-            .map_err(|e| e.into())
+                        Some((decoded, position))
+                    }
+                    None => None,
+                }
+            };
+
+            trace!("Cache read result {:?}", res);
+
+            Ok(res)
+        })
     }
 
-    /// Save an event into the cache
-    pub async fn save<'a, V>(&'a self, key: &'a str, value: &'a V) -> Result<(), io::Error>
+    fn save<'a, V>(&'a self, key: &'a str, value: &'a V, position: i64) -> AdapterFuture<'a, ()>
     where
         V: Serialize + Debug,
     {
         debug!("Cache aggregate result under key {}: {:?}", key, value);
 
-        self.conn
-            .get()
-            .unwrap()
-            .execute(
-                r#"insert into aggregate_cache (id, data, time)
-                    values ($1, $2, now())
-                    on conflict (id)
-                    do update set data = excluded.data, time = now() returning data"#,
-                &[&key, &to_value(value).expect("To value")],
-            )
-            .map(|_| ())
-            .map_err(|e| e.into())
+        let value = to_value(value);
+
+        Box::pin(async move {
+            self.conn
+                .get()?
+                .execute(
+                    &format!(
+                        r#"insert into {} (id, data, time, position)
+                            values ($1, $2, now(), $3)
+                            on conflict (id)
+                            do update set data = excluded.data, time = now(), position = excluded.position
+                            returning data"#,
+                        self.cache
+                    ),
+                    &[&key, &value?, &position],
+                )
+                .map(|_| ())
+                .map_err(|e| e.into())
+        })
+    }
+
+    fn read_snapshot<'a, T>(
+        &'a self,
+        key: SnapshotKey<'a>,
+    ) -> AdapterFuture<'a, Option<CacheResult<T>>>
+    where
+        T: DeserializeOwned + Debug + Send + 'a,
+    {
+        Box::pin(async move {
+            trace!("Snapshot read for {:?}", key);
+
+            let rows = self.conn.get()?.query(
+                &format!(
+                    r#"select data, position from {}
+                        where aggregate_type = $1 and stream_id = $2 and query_id = $3 and version = $4
+                        limit 1"#,
+                    self.snapshots
+                ),
+                &[
+                    &key.aggregate_type,
+                    &key.stream_id,
+                    &key.query_id,
+                    &(key.version as i32),
+                ],
+            )?;
+
+            let res = if rows.len() != 1 {
+                None
+            } else {
+                let row = rows.get(0);
+
+                let decoded: T = from_value(row.get(0)).map_err(|e| {
+                    Error::Serialization(format!(
+                        "Could not decode {} snapshot for stream {}: {}",
+                        key.aggregate_type, key.stream_id, e
+                    ))
+                })?;
+
+                Some((decoded, row.get(1)))
+            };
+
+            trace!("Snapshot read result {:?}", res);
+
+            Ok(res)
+        })
+    }
+
+    fn save_snapshot<'a, V>(
+        &'a self,
+        key: SnapshotKey<'a>,
+        value: &'a V,
+        position: i64,
+    ) -> AdapterFuture<'a, ()>
+    where
+        V: Serialize + Debug,
+    {
+        debug!("Snapshot {:?} at position {}: {:?}", key, position, value);
+
+        let value = to_value(value);
+
+        Box::pin(async move {
+            // Never replace a snapshot with one covering fewer events
+            self.conn
+                .get()?
+                .execute(
+                    &format!(
+                        r#"insert into {0} (aggregate_type, stream_id, query_id, version, position, data, time)
+                            values ($1, $2, $3, $4, $5, $6, now())
+                            on conflict (aggregate_type, stream_id, query_id, version)
+                            do update set position = excluded.position, data = excluded.data, time = now()
+                            where {0}.position <= excluded.position"#,
+                        self.snapshots
+                    ),
+                    &[
+                        &key.aggregate_type,
+                        &key.stream_id,
+                        &key.query_id,
+                        &(key.version as i32),
+                        &position,
+                        &value?,
+                    ],
+                )
+                .map(|_| ())
+                .map_err(|e| e.into())
+        })
     }
 }
@@ -1,79 +1,161 @@
-// TODO: Remove when Redis cache is usable
-#![allow(unused)]
-
-use super::CacheResult;
+use super::{CacheAdapter, CacheResult, SnapshotKey};
+use crate::adapters::AdapterFuture;
+use crate::error::Error;
 use chrono::{DateTime, Utc};
-use log::error;
-use redis::{Client, Commands, Connection};
+use log::{debug, trace};
+use redis::{Client, Commands, Connection, RedisResult, Script};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use serde_json::{from_str, to_string};
-use std::io;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+/// Saves a snapshot item unless the saved snapshot is at a later position, checking and writing
+/// atomically
+const SAVE_SNAPSHOT_SCRIPT: &str = r#"
+    local existing = redis.call('get', KEYS[1])
+
+    if existing then
+        local position = cjson.decode(existing)['position']
+
+        if type(position) == 'number' and position > tonumber(ARGV[2]) then
+            return 0
+        end
+    end
+
+    redis.call('set', KEYS[1], ARGV[1])
+
+    return 1
+"#;
 
 #[derive(Serialize, Deserialize)]
-pub struct RedisCacheItem<D> {
+struct RedisCacheItem<D> {
     data: D,
     time: DateTime<Utc>,
+    position: Option<i64>,
+}
+
+fn poisoned<T>(_: T) -> Error {
+    Error::Connection("Redis connection lock poisoned".into())
 }
 
 /// Redis cache adapter
+///
+/// Clones of an adapter share the same connection.
+#[derive(Clone)]
 pub struct RedisCacheAdapter {
     client: Client,
-    conn: Connection,
+    conn: Arc<Mutex<Option<Connection>>>,
 }
 
-impl Clone for RedisCacheAdapter {
-    fn clone(&self) -> Self {
-        let client = self.client.clone();
-        let conn = client
-            .get_connection()
-            .expect("Could not clone Redis cache adapter");
+impl RedisCacheAdapter {
+    /// Create a new Redis-backed cache from a Redis client handle
+    ///
+    /// A connection is made from the client and shared by every cache operation. If it fails, a
+    /// new one is made for the next operation.
+    pub async fn new(client: Client) -> Result<Self, Error> {
+        let conn = client.get_connection()?;
 
-        Self { client, conn }
+        Ok(Self {
+            client,
+            conn: Arc::new(Mutex::new(Some(conn))),
+        })
+    }
+
+    /// Run a command on the shared connection, reconnecting first if the last command failed
+    fn with_connection<T, F>(&self, command: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Connection) -> RedisResult<T>,
+    {
+        let mut shared = self.conn.lock().map_err(poisoned)?;
+
+        let conn = match shared.take() {
+            Some(conn) => conn,
+            None => self.client.get_connection()?,
+        };
+
+        let result = command(&conn);
+
+        // Drop a broken connection so the next command makes a new one
+        match result {
+            Err(ref err) if err.is_io_error() || err.is_connection_dropped() => (),
+            _ => *shared = Some(conn),
+        }
+
+        result.map_err(Error::from)
     }
 }
 
-impl RedisCacheAdapter {
-    /// Create a new Redis-backed cache from a Redis client handle
-    ///
-    /// A connection to Redis is created from the client each time the adapter is created **or
-    /// cloned**. It should be cloned as little as possible.
-    pub async fn new(client: Client) -> Result<Self, io::Error> {
-        let conn = client
-            .get_connection()
-            .expect("Could not get Redis connection");
-
-        Ok(Self { client, conn })
+impl CacheAdapter for RedisCacheAdapter {
+    fn read<'a, T>(&'a self, key: &'a str) -> AdapterFuture<'a, Option<CacheResult<T>>>
+    where
+        T: DeserializeOwned + Debug + Send + 'a,
+    {
+        Box::pin(async move {
+            trace!("Cache read key {}", key);
+
+            let value: Option<String> = self.with_connection(|conn| conn.get(key))?;
+
+            // Items cached before positions were recorded have no position and are rebuilt
+            let res = value
+                .map(|value| from_str::<RedisCacheItem<T>>(&value))
+                .transpose()?
+                .and_then(|parsed| parsed.position.map(|position| (parsed.data, position)));
+
+            trace!("Cache read result {:?}", res);
+
+            Ok(res)
+        })
     }
 
-    pub async fn set<'a, V>(&'a self, id: String, data: V) -> Result<(), String>
+    fn save<'a, V>(&'a self, key: &'a str, value: &'a V, position: i64) -> AdapterFuture<'a, ()>
     where
-        V: Serialize + 'a,
+        V: Serialize + Debug,
     {
-        let time = Utc::now();
+        debug!("Cache aggregate result under key {}: {:?}", key, value);
+
+        let item = to_string(&RedisCacheItem {
+            data: value,
+            time: Utc::now(),
+            position: Some(position),
+        });
 
-        // TODO: Error handling
-        let value: String = to_string(&RedisCacheItem { time, data })
-            .expect("Failed to serialize Redis cache item");
+        Box::pin(async move {
+            let item = item?;
 
-        self.conn.set(id, value).map_err(|err| {
-            error!("Failed to set cache item: {:?}", err);
+            let _: () = self.with_connection(|conn| conn.set(key, item))?;
 
-            "Failed to set cache item".into()
+            Ok(())
         })
     }
 
-    // TODO: Error handling
-    pub async fn get<T>(&self, key: String) -> Result<Option<CacheResult<T>>, String>
+    fn save_snapshot<'a, V>(
+        &'a self,
+        key: SnapshotKey<'a>,
+        value: &'a V,
+        position: i64,
+    ) -> AdapterFuture<'a, ()>
     where
-        T: DeserializeOwned,
+        V: Serialize + Debug,
     {
-        let value: Option<String> = self.conn.get(key).unwrap();
+        debug!("Snapshot {:?} at position {}: {:?}", key, position, value);
 
-        Ok(value.map(|value| {
-            let parsed: RedisCacheItem<T> = from_str(&value).unwrap();
+        let cache_key = key.cache_key();
+        let item = to_string(&RedisCacheItem {
+            data: value,
+            time: Utc::now(),
+            position: Some(position),
+        });
 
-            (parsed.data, parsed.time)
-        }))
+        Box::pin(async move {
+            let script = Script::new(SAVE_SNAPSHOT_SCRIPT);
+            let mut invocation = script.key(cache_key);
+
+            invocation.arg(item?).arg(position);
+
+            let _: i32 = self.with_connection(|conn| invocation.invoke(conn))?;
+
+            Ok(())
+        })
     }
 }
@@ -0,0 +1,127 @@
+use super::{CacheAdapter, CacheResult, SnapshotKey};
+use crate::adapters::store::poisoned;
+use crate::adapters::AdapterFuture;
+use crate::error::Error;
+use chrono::prelude::*;
+use log::{debug, trace};
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{from_value, to_value, Value as JsonValue};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+const INIT_QUERIES: &str = r#"
+-- Create cache table if it doesn't already exist
+create table if not exists aggregate_cache(
+    id varchar(64) not null,
+    data text not null,
+    time text,
+    position integer,
+    primary key(id)
+);
+
+create index if not exists cache_time on aggregate_cache (time desc);
+"#;
+
+/// SQLite-backed cache adapter
+#[derive(Clone)]
+pub struct SqliteCacheAdapter {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteCacheAdapter {
+    /// Create a new SQLite-backed cache adapter instance
+    ///
+    /// This will attempt to create the cache table if it does not already exist
+    pub async fn new(conn: Arc<Mutex<Connection>>) -> Result<Self, Error> {
+        conn.lock().map_err(poisoned)?.execute_batch(INIT_QUERIES)?;
+
+        Ok(Self { conn })
+    }
+}
+
+impl CacheAdapter for SqliteCacheAdapter {
+    fn read<'a, T>(&'a self, key: &'a str) -> AdapterFuture<'a, Option<CacheResult<T>>>
+    where
+        T: DeserializeOwned + Debug + Send + 'a,
+    {
+        Box::pin(async move {
+            trace!("Cache read key {}", key);
+
+            let row: Option<(JsonValue, Option<i64>)> = self
+                .conn
+                .lock()
+                .map_err(poisoned)?
+                .query_row(
+                    "select data, position from aggregate_cache where id = ?1 limit 1",
+                    &[&key],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+
+            // Items cached before positions were recorded have no position and are rebuilt
+            let res = row
+                .and_then(|(data, position)| position.map(|position| (data, position)))
+                .map(|(data, position)| from_value(data).map(|decoded: T| (decoded, position)))
+                .transpose()?;
+
+            trace!("Cache read result {:?}", res);
+
+            Ok(res)
+        })
+    }
+
+    fn save<'a, V>(&'a self, key: &'a str, value: &'a V, position: i64) -> AdapterFuture<'a, ()>
+    where
+        V: Serialize + Debug,
+    {
+        debug!("Cache aggregate result under key {}: {:?}", key, value);
+
+        let value = to_value(value);
+
+        Box::pin(async move {
+            self.conn
+                .lock()
+                .map_err(poisoned)?
+                .execute(
+                    "insert or replace into aggregate_cache (id, data, time, position) values (?1, ?2, ?3, ?4)",
+                    &[&key as &ToSql, &value?, &Utc::now(), &position],
+                )
+                .map(|_| ())
+                .map_err(Error::from)
+        })
+    }
+
+    fn save_snapshot<'a, V>(
+        &'a self,
+        key: SnapshotKey<'a>,
+        value: &'a V,
+        position: i64,
+    ) -> AdapterFuture<'a, ()>
+    where
+        V: Serialize + Debug,
+    {
+        debug!("Snapshot {:?} at position {}: {:?}", key, position, value);
+
+        let cache_key = key.cache_key();
+        let value = to_value(value);
+
+        Box::pin(async move {
+            // Never replace a snapshot with one covering fewer events
+            self.conn
+                .lock()
+                .map_err(poisoned)?
+                .execute(
+                    r#"insert into aggregate_cache (id, data, time, position) values (?1, ?2, ?3, ?4)
+                        on conflict (id)
+                        do update set data = excluded.data, time = excluded.time, position = excluded.position
+                        where aggregate_cache.position is null or aggregate_cache.position <= excluded.position"#,
+                    &[&cache_key as &ToSql, &value?, &Utc::now(), &position],
+                )
+                .map(|_| ())
+                .map_err(Error::from)
+        })
+    }
+}
@@ -1,24 +1,20 @@
-use crate::event::Event;
-use crate::event_handler::EventHandler;
+use super::{EmitterAdapter, EmitterMessage, MessageStream};
+use crate::adapters::AdapterFuture;
+use crate::error::Error;
 use crate::internals::forward;
-use crate::store::Store;
-use event_store_derive_internals::EventData;
-use futures::Future;
+use futures::{Future, Stream};
 use lapin_futures::channel::{
     BasicConsumeOptions, BasicProperties, BasicPublishOptions, Channel, ExchangeDeclareOptions,
     QueueBindOptions, QueueDeclareOptions,
 };
 use lapin_futures::client::{Client, ConnectionOptions};
 use lapin_futures::consumer::Consumer;
+use lapin_futures::message::Delivery;
 use lapin_futures::queue::Queue;
 use lapin_futures::types::FieldTable;
-use log::{debug, error, info, trace};
-use serde_json::Value as JsonValue;
-use std::fmt::Debug;
-use std::io;
+use log::{debug, info, trace};
 use std::net::ToSocketAddrs;
 use tokio::net::TcpStream;
-use tokio_async_await::stream::StreamExt;
 use url::Url;
 
 /// AMQP-backed emitter/subscriber
@@ -32,13 +28,8 @@ pub struct AmqpEmitterAdapter {
 
 impl AmqpEmitterAdapter {
     /// Create a new AMQP emitter/subscriber
-    pub async fn new(
-        url: &str,
-        exchange: String,
-        store_namespace: String,
-    ) -> Result<Self, io::Error> {
-        let url =
-            Url::parse(url).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    pub async fn new(url: &str, exchange: String, store_namespace: String) -> Result<Self, Error> {
+        let url = Url::parse(url).map_err(|e| Error::Connection(e.to_string()))?;
 
         let channel = await!(amqp_connect(&url, &exchange))?;
 
@@ -50,154 +41,123 @@ impl AmqpEmitterAdapter {
         })
     }
 
-    /// Subscribe to an event
-    ///
-    /// If the handler for an event fails, the event on the queue will not be acked
-    pub async fn subscribe<ED>(&self, store: Store) -> Result<(), io::Error>
-    where
-        ED: EventData + EventHandler + Debug + Send,
-    {
-        let channel = await!(amqp_connect(&self.url, &self.exchange))?;
-
-        let event_namespace = ED::event_namespace();
-        let event_type = ED::event_type();
-        let event_name = format!("{}.{}", event_namespace, event_type);
-        let queue_name = self.namespaced_event_queue_name::<ED>();
-
-        trace!("Subscribe queue {}", queue_name);
-
-        let queue = await!(amqp_bind_queue(
-            &channel,
-            &queue_name,
-            &self.exchange,
-            &event_name
-        ))
-        .unwrap();
-
-        info!(
-            "Creating consumer for event {} on queue {} on exchange {}",
-            event_name, queue_name, self.exchange
-        );
-
-        let mut stream: Consumer<TcpStream> = await!(forward(
-            channel
-                .basic_consume(
-                    &queue,
-                    &"",
-                    BasicConsumeOptions::default(),
-                    FieldTable::new(),
-                )
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string())),
-        ))
-        .unwrap();
-
-        // TODO: Move this logic out into subscribable_store to dedupe it from backing stores
-        tokio::spawn_async(async move {
-            while let Some(Ok(message)) = await!(stream.next()) {
-                let parsed = serde_json::from_slice::<Event<ED>>(&message.data);
-
-                match parsed {
-                    Ok(event) => {
-                        let event_id = event.id;
-
-                        trace!("Received event {}", event_id);
-
-                        if let Ok(_) = ED::handle_event(event, &store) {
-                            trace!("Ack event {}", message.delivery_tag);
-
-                            await!(forward(channel.basic_ack(message.delivery_tag, false)))
-                                .expect("Could not ack message");
-                        } else {
-                            error!(
-                                "Failed to handle event ID {}, not acking queue item",
-                                event_id
-                            );
-                        }
-                    }
-                    Err(e) => {
-                        trace!(
-                            "Failed event payload: {}",
-                            String::from_utf8(message.data.clone())
-                                .unwrap_or(String::from("(failed to decode message)"))
-                        );
-
-                        serde_json::from_slice::<JsonValue>(&message.data)
-                            .map(|evt| {
-                                error!(
-                                    "Failed to parse event {} (ID {}): {}",
-                                    ED::event_namespace_and_type(),
-                                    evt["id"],
-                                    e.to_string()
-                                );
-                            })
-                            .unwrap_or_else(|_| {
-                                error!(
-                                    "Failed to parse event {} (ID unknown): {}",
-                                    ED::event_namespace_and_type(),
-                                    e.to_string()
-                                );
-                            });
-                    }
-                }
-            }
-        });
-
-        Ok(())
+    fn namespaced_event_queue_name(&self, routing_key: &str) -> String {
+        format!("{}-{}", self.store_namespace, routing_key)
     }
+}
 
-    /// Emit an event
-    pub async fn emit<'a, ED>(&'a self, event: &'a Event<ED>) -> Result<(), io::Error>
-    where
-        ED: EventData,
-    {
-        let payload: Vec<u8> = serde_json::to_string(&event)
-            .expect("Cant serialise event")
-            .into();
-
-        let event_namespace = ED::event_namespace();
-        let event_type = ED::event_type();
-        let event_name = format!("{}.{}", event_namespace, event_type);
-        let queue_name = self.namespaced_event_queue_name::<ED>();
-
-        info!(
-            "Emitting event {} onto exchange {} through queue {}",
-            event_name, self.exchange, queue_name
-        );
-
-        await!(amqp_emit_data(
-            &self.channel,
-            &self.exchange,
-            &event_name,
-            payload
-        ))?;
+impl EmitterAdapter for AmqpEmitterAdapter {
+    type Message = AmqpMessage;
+
+    fn publish<'a>(&'a self, routing_key: &'a str, payload: Vec<u8>) -> AdapterFuture<'a, ()> {
+        Box::pin(async move {
+            info!(
+                "Emitting event {} onto exchange {}",
+                routing_key, self.exchange
+            );
+
+            await!(amqp_emit_data(
+                &self.channel,
+                &self.exchange,
+                routing_key,
+                payload
+            ))
+        })
+    }
+
+    fn consume<'a>(
+        &'a self,
+        routing_key: &'a str,
+    ) -> AdapterFuture<'a, MessageStream<AmqpMessage>> {
+        Box::pin(async move {
+            let channel = await!(amqp_connect(&self.url, &self.exchange))?;
+
+            let queue_name = self.namespaced_event_queue_name(routing_key);
+
+            trace!("Subscribe queue {}", queue_name);
+
+            let queue = await!(amqp_bind_queue(
+                &channel,
+                &queue_name,
+                &self.exchange,
+                routing_key
+            ))?;
+
+            info!(
+                "Creating consumer for event {} on queue {} on exchange {}",
+                routing_key, queue_name, self.exchange
+            );
+
+            let stream: Consumer<TcpStream> = await!(forward(
+                channel
+                    .basic_consume(
+                        &queue,
+                        &"",
+                        BasicConsumeOptions::default(),
+                        FieldTable::new(),
+                    )
+                    .map_err(|e| Error::Connection(e.to_string())),
+            ))?;
+
+            let messages: MessageStream<AmqpMessage> = Box::new(
+                stream
+                    .map(move |delivery| AmqpMessage {
+                        channel: channel.clone(),
+                        delivery,
+                    })
+                    .map_err(|e| Error::Connection(e.to_string())),
+            );
+
+            Ok(messages)
+        })
+    }
+}
 
-        Ok(())
+/// A message received from an AMQP queue
+pub struct AmqpMessage {
+    channel: Channel<TcpStream>,
+    delivery: Delivery,
+}
+
+impl EmitterMessage for AmqpMessage {
+    fn payload(&self) -> &[u8] {
+        &self.delivery.data
     }
 
-    fn namespaced_event_queue_name<ED>(&self) -> String
-    where
-        ED: EventData,
-    {
-        format!("{}-{}", self.store_namespace, self.event_queue_name::<ED>())
+    fn ack(self) -> AdapterFuture<'static, ()> {
+        Box::pin(async move {
+            trace!("Ack event {}", self.delivery.delivery_tag);
+
+            await!(forward(
+                self.channel.basic_ack(self.delivery.delivery_tag, false)
+            ))
+            .map_err(|e| Error::Connection(e.to_string()))
+        })
     }
 
-    fn event_queue_name<ED>(&self) -> String
-    where
-        ED: EventData,
-    {
-        format!("{}.{}", ED::event_namespace(), ED::event_type())
+    fn reject(self, requeue: bool) -> AdapterFuture<'static, ()> {
+        Box::pin(async move {
+            trace!(
+                "Reject event {}, requeue {}",
+                self.delivery.delivery_tag,
+                requeue
+            );
+
+            await!(forward(
+                self.channel
+                    .basic_reject(self.delivery.delivery_tag, requeue)
+            ))
+            .map_err(|e| Error::Connection(e.to_string()))
+        })
     }
 }
 
-async fn amqp_connect<'a>(
-    url: &'a Url,
-    exchange: &'a String,
-) -> Result<Channel<TcpStream>, io::Error> {
+async fn amqp_connect<'a>(url: &'a Url, exchange: &'a String) -> Result<Channel<TcpStream>, Error> {
     let exchange1 = exchange.clone();
 
     let host = url
         .host_str()
-        .ok_or(io::Error::new(io::ErrorKind::Other, "Host str".to_string()))?;
+        .ok_or(Error::Connection("Host str".to_string()))?;
 
     let port = url.port().unwrap_or(5672);
 
@@ -207,10 +167,9 @@ async fn amqp_connect<'a>(
 
     let sock_addr = host_port
         .to_socket_addrs()
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?
+        .map_err(|e| Error::Connection(e.to_string()))?
         .next()
-        .ok_or(io::Error::new(
-            io::ErrorKind::Other,
+        .ok_or(Error::Connection(
             "RabbitMQ hostname resolved to 0 IPs".to_string(),
         ))?;
 
@@ -231,12 +190,12 @@ async fn amqp_connect<'a>(
     let stream: TcpStream = await!(forward(TcpStream::connect(&sock_addr)))?;
 
     let (client, heartbeat) = await!(forward(Client::connect(stream, options)))
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        .map_err(|e| Error::Connection(e.to_string()))?;
 
     tokio::spawn(heartbeat.map_err(|e| eprintln!("heartbeat error: {:?}", e)));
 
-    let channel = await!(forward(client.create_channel()))
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    let channel =
+        await!(forward(client.create_channel())).map_err(|e| Error::Connection(e.to_string()))?;
 
     await!(forward(channel.exchange_declare(
         &exchange1,
@@ -247,17 +206,17 @@ async fn amqp_connect<'a>(
         },
         FieldTable::new(),
     )))
-    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    .map_err(|e| Error::Connection(e.to_string()))?;
 
     Ok(channel)
 }
 
 async fn amqp_bind_queue<'a>(
     channel: &'a Channel<TcpStream>,
-    queue_name: &'a String,
-    exchange_name: &'a String,
-    routing_key: &'a String,
-) -> Result<Queue, io::Error> {
+    queue_name: &'a str,
+    exchange_name: &'a str,
+    routing_key: &'a str,
+) -> Result<Queue, Error> {
     debug!(
         "Bind queue {} to exchange {} through routing key {}",
         queue_name, exchange_name, routing_key
@@ -273,7 +232,7 @@ async fn amqp_bind_queue<'a>(
         },
         FieldTable::new(),
     )))
-    .unwrap();
+    .map_err(|e| Error::Connection(e.to_string()))?;
 
     await!(forward(
         channel
@@ -284,7 +243,7 @@ async fn amqp_bind_queue<'a>(
                 QueueBindOptions::default(),
                 FieldTable::new(),
             )
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+            .map_err(|e| Error::Connection(e.to_string()))
     ))?;
 
     Ok(queue)
@@ -292,10 +251,10 @@ async fn amqp_bind_queue<'a>(
 
 async fn amqp_emit_data<'a>(
     channel: &'a Channel<TcpStream>,
-    exchange: &'a String,
-    routing_key: &'a String,
+    exchange: &'a str,
+    routing_key: &'a str,
     payload: Vec<u8>,
-) -> Result<(), io::Error> {
+) -> Result<(), Error> {
     debug!(
         "Emitting payload through routing key {} onto exchange {}",
         routing_key, exchange
@@ -310,7 +269,7 @@ async fn amqp_emit_data<'a>(
                 BasicPublishOptions::default(),
                 BasicProperties::default(),
             )
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+            .map_err(|e| Error::Connection(e.to_string()))
     ))?;
 
     Ok(())
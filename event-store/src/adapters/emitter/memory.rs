@@ -0,0 +1,99 @@
+use super::{EmitterAdapter, EmitterMessage, MessageStream};
+use crate::adapters::AdapterFuture;
+use crate::error::Error;
+use futures::sync::mpsc::{unbounded, UnboundedSender};
+use futures::Stream;
+use log::{info, trace};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// In-process emitter/subscriber
+///
+/// Every published payload is broadcast to all subscribers of its routing key. Stores that should
+/// receive each other's events must be created with clones of the same adapter. Intended for use
+/// in tests.
+#[derive(Clone, Default)]
+pub struct MemoryEmitterAdapter {
+    subscribers: Arc<Mutex<HashMap<String, Vec<UnboundedSender<MemoryMessage>>>>>,
+}
+
+impl MemoryEmitterAdapter {
+    /// Create a new in-process emitter with no subscribers
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn poisoned<T>(_: T) -> Error {
+    Error::Connection("Memory emitter lock poisoned".into())
+}
+
+impl EmitterAdapter for MemoryEmitterAdapter {
+    type Message = MemoryMessage;
+
+    fn publish<'a>(&'a self, routing_key: &'a str, payload: Vec<u8>) -> AdapterFuture<'a, ()> {
+        Box::pin(async move {
+            info!("Emitting event {} in process", routing_key);
+
+            let mut subscribers = self.subscribers.lock().map_err(poisoned)?;
+
+            if let Some(senders) = subscribers.get_mut(routing_key) {
+                // Subscribers whose receiving end has been dropped are removed
+                senders.retain(|sender| {
+                    sender
+                        .unbounded_send(MemoryMessage {
+                            payload: payload.clone(),
+                        })
+                        .is_ok()
+                });
+            }
+
+            Ok(())
+        })
+    }
+
+    fn consume<'a>(
+        &'a self,
+        routing_key: &'a str,
+    ) -> AdapterFuture<'a, MessageStream<MemoryMessage>> {
+        Box::pin(async move {
+            trace!("Subscribe to {} in process", routing_key);
+
+            let (sender, receiver) = unbounded();
+
+            self.subscribers
+                .lock()
+                .map_err(poisoned)?
+                .entry(routing_key.into())
+                .or_insert_with(Vec::new)
+                .push(sender);
+
+            let messages: MessageStream<MemoryMessage> = Box::new(
+                receiver.map_err(|_| Error::Connection("Memory emitter channel closed".into())),
+            );
+
+            Ok(messages)
+        })
+    }
+}
+
+/// A message received from a [`MemoryEmitterAdapter`]
+pub struct MemoryMessage {
+    payload: Vec<u8>,
+}
+
+impl EmitterMessage for MemoryMessage {
+    fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    fn ack(self) -> AdapterFuture<'static, ()> {
+        // Messages are never redelivered, so there is nothing to acknowledge
+        Box::pin(async { Ok(()) })
+    }
+
+    fn reject(self, _requeue: bool) -> AdapterFuture<'static, ()> {
+        // Messages cannot be redelivered, so rejected messages are dropped
+        Box::pin(async { Ok(()) })
+    }
+}
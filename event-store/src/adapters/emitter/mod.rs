@@ -1,3 +1,107 @@
 mod amqp;
+mod memory;
+mod pg;
 
-pub use self::amqp::AmqpEmitterAdapter;
+pub use self::amqp::{AmqpEmitterAdapter, AmqpMessage};
+pub use self::memory::{MemoryEmitterAdapter, MemoryMessage};
+pub use self::pg::{PgEmitterAdapter, PgMessage};
+
+use crate::adapters::{AdapterFuture, CacheAdapter, EventRecord, StoreAdapter};
+use crate::error::Error;
+use crate::event::Event;
+use crate::event_handler::EventHandler;
+use crate::store::Store;
+use crate::subscribable_store::dispatch_messages;
+use event_store_derive_internals::EventData;
+use futures::Stream;
+use log::info;
+use serde::Serialize;
+use std::fmt::Debug;
+
+/// Stream of raw messages received by an emitter
+pub type MessageStream<M> = Box<dyn Stream<Item = M, Error = Error> + Send>;
+
+/// A raw message received from an emitter's transport
+pub trait EmitterMessage: Send + 'static {
+    /// The message body. This is an event serialized to JSON.
+    fn payload(&self) -> &[u8];
+
+    /// Acknowledge that this message has been handled and should not be delivered again
+    fn ack(self) -> AdapterFuture<'static, ()>;
+
+    /// Reject a message that could not be handled
+    ///
+    /// If `requeue` is `true` the message should be delivered again later, if the transport
+    /// supports it. Otherwise the message is discarded.
+    fn reject(self, requeue: bool) -> AdapterFuture<'static, ()>;
+}
+
+/// A transport that events are emitted to and received from
+///
+/// Implementations only need to publish and consume raw payloads; decoding events, calling
+/// [`crate::EventHandler::handle_event`] and deciding whether to ack a message is shared between
+/// all emitters.
+pub trait EmitterAdapter: Clone + Send + Sync + 'static {
+    /// The type of message received by [`EmitterAdapter::consume`]
+    type Message: EmitterMessage;
+
+    /// Publish a payload with a routing key of the form `event_namespace.event_type`
+    fn publish<'a>(&'a self, routing_key: &'a str, payload: Vec<u8>) -> AdapterFuture<'a, ()>;
+
+    /// Start consuming messages published with the given routing key
+    fn consume<'a>(
+        &'a self,
+        routing_key: &'a str,
+    ) -> AdapterFuture<'a, MessageStream<Self::Message>>;
+
+    /// Emit an event to all subscribers of its namespace and type
+    fn emit<'a, ED, S>(&'a self, event: &'a Event<ED, S>) -> AdapterFuture<'a, ()>
+    where
+        ED: EventData,
+        S: Serialize,
+    {
+        let payload = serde_json::to_vec(&event);
+
+        Box::pin(async move { await!(self.publish(ED::event_namespace_and_type(), payload?)) })
+    }
+
+    /// Emit a saved event record to all subscribers of its namespace and type
+    fn emit_record<'a>(&'a self, record: &'a EventRecord) -> AdapterFuture<'a, ()> {
+        let json = record.to_json();
+        let payload = serde_json::to_vec(&json);
+        let routing_key = record
+            .event_namespace_and_type()
+            .ok_or_else(|| Error::decode(&json, "Event data has no namespace or type"));
+
+        Box::pin(async move {
+            let routing_key = routing_key?;
+
+            await!(self.publish(&routing_key, payload?))
+        })
+    }
+
+    /// Subscribe to events matching the namespace and type in `ED`
+    ///
+    /// Received events are passed to `ED::handle_event` in a spawned task. If the handler returns
+    /// [`crate::HandlerError::Retry`] the message is requeued after a short delay, otherwise failed
+    /// messages are discarded.
+    fn subscribe<'a, ED, SA, CA>(&'a self, store: Store<SA, CA, Self>) -> AdapterFuture<'a, ()>
+    where
+        ED: EventHandler + Debug + Send + 'static,
+        SA: StoreAdapter,
+        CA: CacheAdapter,
+    {
+        Box::pin(async move {
+            let messages = await!(self.consume(ED::event_namespace_and_type()))?;
+
+            info!(
+                "Dispatching events {} to handler",
+                ED::event_namespace_and_type()
+            );
+
+            tokio::spawn_async(dispatch_messages::<ED, SA, CA, Self>(messages, store));
+
+            Ok(())
+        })
+    }
+}
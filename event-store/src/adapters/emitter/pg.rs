@@ -0,0 +1,262 @@
+use super::{EmitterAdapter, EmitterMessage, MessageStream};
+use crate::adapters::{AdapterFuture, PgStoreTables};
+use crate::error::Error;
+use fallible_iterator::FallibleIterator;
+use futures::sync::mpsc::{unbounded, UnboundedSender};
+use futures::Stream;
+use log::{debug, error, info, trace, warn};
+use postgres::Connection;
+use r2d2::{ManageConnection, Pool};
+use r2d2_postgres::PostgresConnectionManager;
+use serde_json::{json, Value as JsonValue};
+use std::sync::Arc;
+use std::thread;
+use uuid::Uuid;
+
+/// Postgres limits notification payloads to 8000 bytes. Larger events are sent as a pointer to the
+/// saved row instead.
+const MAX_NOTIFY_PAYLOAD_BYTES: usize = 7999;
+
+/// Quote a routing key for use as a channel name in a `LISTEN` statement
+fn quote_channel(routing_key: &str) -> String {
+    format!("\"{}\"", routing_key.replace('"', "\"\""))
+}
+
+/// Emitter/subscriber backed by Postgres' `LISTEN`/`NOTIFY`
+///
+/// Events are sent with `pg_notify()` on a channel named after the event's `namespace.type`, the
+/// same as the AMQP routing keys. Events larger than the notification payload limit are sent as a
+/// pointer to their row in the events table, so they must be saved before being emitted. Use
+/// [`PgEmitterAdapter::with_store_tables`] if the store uses a non-default schema or table name.
+///
+/// Notifications are not persisted: subscribers will not receive events emitted while they are
+/// disconnected, and messages cannot be redelivered, so events rejected with `requeue` set are
+/// dropped.
+#[derive(Clone)]
+pub struct PgEmitterAdapter {
+    conn: Pool<PostgresConnectionManager>,
+    listen: Arc<PostgresConnectionManager>,
+    events: String,
+}
+
+impl PgEmitterAdapter {
+    /// Create a new Postgres emitter/subscriber
+    ///
+    /// Events are published and fetched through `conn`. Each subscription holds its own
+    /// connection, opened with `listen`, for as long as it's listening, so subscribing doesn't
+    /// take connections out of the pool. `listen` should connect to the same database as `conn`.
+    pub async fn new(
+        conn: Pool<PostgresConnectionManager>,
+        listen: PostgresConnectionManager,
+    ) -> Result<Self, Error> {
+        // Check that a connection can be made
+        conn.get()?;
+
+        Ok(Self {
+            conn,
+            listen: Arc::new(listen),
+            events: PgStoreTables::default().events(),
+        })
+    }
+
+    /// Fetch events sent as pointers from the events table of a store using the given tables
+    pub fn with_store_tables(self, tables: &PgStoreTables) -> Self {
+        Self {
+            events: tables.events(),
+            ..self
+        }
+    }
+}
+
+impl EmitterAdapter for PgEmitterAdapter {
+    type Message = PgMessage;
+
+    fn publish<'a>(&'a self, routing_key: &'a str, payload: Vec<u8>) -> AdapterFuture<'a, ()> {
+        Box::pin(async move {
+            info!("Emitting event {} through pg_notify", routing_key);
+
+            let payload = if payload.len() > MAX_NOTIFY_PAYLOAD_BYTES {
+                let event: JsonValue = serde_json::from_slice(&payload)?;
+
+                debug!(
+                    "Event {} is too large to notify, sending pointer instead",
+                    event["id"]
+                );
+
+                json!({ "event_id": event["id"] }).to_string()
+            } else {
+                String::from_utf8(payload).map_err(|e| Error::Serialization(e.to_string()))?
+            };
+
+            self.conn
+                .get()?
+                .execute("select pg_notify($1, $2)", &[&routing_key, &payload])?;
+
+            Ok(())
+        })
+    }
+
+    fn consume<'a>(&'a self, routing_key: &'a str) -> AdapterFuture<'a, MessageStream<PgMessage>> {
+        Box::pin(async move {
+            let listen_conn = self.listen.connect()?;
+
+            listen_conn.batch_execute(&format!("listen {}", quote_channel(routing_key)))?;
+
+            info!("Listening for event {}", routing_key);
+
+            let (sender, receiver) = unbounded();
+            let pool = self.conn.clone();
+            let routing_key = routing_key.to_string();
+            let events = self.events.clone();
+
+            // The connection is closed when the listener stops
+            thread::spawn(move || listen(&listen_conn, &pool, &events, &sender, &routing_key));
+
+            let messages: MessageStream<PgMessage> =
+                Box::new(receiver.map_err(|_| Error::Connection("Listener stopped".into())));
+
+            Ok(messages)
+        })
+    }
+}
+
+/// Forward notifications to the subscriber until it goes away or the connection fails
+fn listen(
+    conn: &Connection,
+    pool: &Pool<PostgresConnectionManager>,
+    events: &str,
+    sender: &UnboundedSender<PgMessage>,
+    routing_key: &str,
+) {
+    let notifications = conn.notifications();
+    let mut it = notifications.blocking_iter();
+
+    loop {
+        match it.next() {
+            Ok(Some(notification)) => {
+                trace!("Notification received on {}", notification.channel);
+
+                if !forward_notification(pool, events, sender, routing_key, notification.payload) {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                error!("Stopped listening for event {}: {}", routing_key, e);
+
+                break;
+            }
+        }
+    }
+}
+
+/// Send a notification payload to the subscriber, fetching the full event if the payload is a
+/// pointer
+///
+/// Returns `false` if the subscriber has gone away and listening should stop.
+fn forward_notification(
+    pool: &Pool<PostgresConnectionManager>,
+    events: &str,
+    sender: &UnboundedSender<PgMessage>,
+    routing_key: &str,
+    payload: String,
+) -> bool {
+    match resolve_payload(pool, events, payload) {
+        Ok(payload) => sender
+            .unbounded_send(PgMessage {
+                routing_key: routing_key.to_string(),
+                payload,
+            })
+            .is_ok(),
+        Err(e) => {
+            error!("Failed to fetch event for notification: {}", e);
+
+            true
+        }
+    }
+}
+
+fn resolve_payload(
+    pool: &Pool<PostgresConnectionManager>,
+    events: &str,
+    payload: String,
+) -> Result<Vec<u8>, Error> {
+    let pointer = serde_json::from_str::<JsonValue>(&payload)
+        .ok()
+        .and_then(|value| {
+            value["event_id"]
+                .as_str()
+                .and_then(|id| Uuid::parse_str(id).ok())
+        });
+
+    let event_id = match pointer {
+        Some(event_id) => event_id,
+        None => return Ok(payload.into_bytes()),
+    };
+
+    trace!("Fetch event {} from notification pointer", event_id);
+
+    let rows = pool.get()?.query(
+        &format!(
+            "select id, data, context, stream_id, sequence, position from {} where id = $1 limit 1",
+            events
+        ),
+        &[&event_id],
+    )?;
+
+    if rows.len() != 1 {
+        return Err(Error::Decode {
+            id: Some(event_id),
+            event_namespace_and_type: None,
+            reason: "Event referenced by notification not found in store".into(),
+        });
+    }
+
+    let row = rows.get(0);
+    let id: Uuid = row.get(0);
+    let data: JsonValue = row.get(1);
+    let context: JsonValue = row.get(2);
+    let stream_id: Option<String> = row.get(3);
+    let sequence: Option<i64> = row.get(4);
+    let position: i64 = row.get(5);
+
+    Ok(json!({
+        "id": id,
+        "data": data,
+        "context": context,
+        "stream_id": stream_id,
+        "sequence": sequence,
+        "position": position,
+    })
+    .to_string()
+    .into_bytes())
+}
+
+/// A message received from a Postgres notification
+pub struct PgMessage {
+    routing_key: String,
+    payload: Vec<u8>,
+}
+
+impl EmitterMessage for PgMessage {
+    fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    fn ack(self) -> AdapterFuture<'static, ()> {
+        // Notifications are never redelivered, so there is nothing to acknowledge
+        Box::pin(async { Ok(()) })
+    }
+
+    fn reject(self, requeue: bool) -> AdapterFuture<'static, ()> {
+        // Notifications cannot be redelivered, so rejected messages are dropped
+        if requeue {
+            warn!(
+                "Dropping event {} rejected for retry, Postgres notifications can't be redelivered",
+                self.routing_key
+            );
+        }
+
+        Box::pin(async { Ok(()) })
+    }
+}
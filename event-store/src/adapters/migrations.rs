@@ -0,0 +1,171 @@
+//! Versioned schema migrations for Postgres-backed adapters
+
+use crate::error::Error;
+use log::{debug, info};
+use postgres::transaction::Transaction;
+use postgres::Connection;
+
+/// Class of the advisory locks held while migrations are applied, so that adapters starting at
+/// the same time don't race to apply the same migration
+///
+/// Each lock is keyed by this class and a hash of the schema-qualified name of what it protects,
+/// so migrations in unrelated schemas don't wait for each other.
+const MIGRATION_LOCK_CLASS: i32 = 0x6576_6e74;
+
+const CREATE_MIGRATIONS_TABLE: &str = r#"
+create table if not exists {migrations}(
+    migration_set varchar(64) not null,
+    version integer not null,
+    description text not null,
+    applied_at timestamp with time zone not null default now(),
+    primary key(migration_set, version)
+);
+"#;
+
+/// Quote an identifier such as a table or schema name for use in a query
+pub(crate) fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Quote a string for use as a literal in a query
+pub(crate) fn quote_literal(literal: &str) -> String {
+    format!("'{}'", literal.replace('\'', "''"))
+}
+
+/// Quote a table name, qualifying it with `schema` if one is given
+pub(crate) fn qualify(schema: Option<&str>, name: &str) -> String {
+    match schema {
+        Some(schema) => format!("{}.{}", quote_ident(schema), quote_ident(name)),
+        None => quote_ident(name),
+    }
+}
+
+/// An SQL expression for the name of `schema`, or the current schema if none is given
+pub(crate) fn schema_literal(schema: Option<&str>) -> String {
+    schema
+        .map(quote_literal)
+        .unwrap_or_else(|| String::from("current_schema()"))
+}
+
+/// A single versioned schema change
+///
+/// Migrations are applied in version order and recorded in the `schema_migrations` table of the
+/// schema they're applied to so each is only applied once. Released migrations must not be
+/// edited; add a new one instead. The first migrations of a set use `if not exists` so they can be
+/// applied to databases created before migrations were recorded.
+pub(crate) struct Migration {
+    /// Version number, unique within a migration set
+    pub version: i32,
+
+    /// Short description, recorded alongside the version
+    pub description: &'static str,
+
+    /// SQL statements to run, with `{name}` placeholders for the variables passed to [`migrate`]
+    pub sql: &'static str,
+}
+
+/// Take the advisory lock for `name` in `schema`, held until `trans` ends
+fn lock_migrations(trans: &Transaction, schema: Option<&str>, name: &str) -> Result<(), Error> {
+    trans.execute(
+        r#"select pg_advisory_xact_lock(
+            $1,
+            hashtext(coalesce($2::text, current_schema()::text) || '.' || $3::text)
+        )"#,
+        &[&MIGRATION_LOCK_CLASS, &schema, &name],
+    )?;
+
+    Ok(())
+}
+
+/// Apply any migrations from `migrations` that have not yet been applied to `schema`, creating
+/// the schema if it doesn't exist
+///
+/// `migration_set` names the set in the schema's migrations table. Adapters name their set after
+/// their main table, so sets for differently named tables in one schema are applied and locked
+/// separately. Each `(name, value)` pair in `vars` replaces `{name}` in the migrations' SQL.
+///
+/// All pending migrations are applied in a single transaction while holding an advisory lock on
+/// the schema-qualified `migration_set`, so either every pending migration is applied or none are.
+pub(crate) fn migrate(
+    conn: &Connection,
+    schema: Option<&str>,
+    migration_set: &str,
+    migrations: &[Migration],
+    vars: &[(&str, String)],
+) -> Result<(), Error> {
+    let migrations_table = qualify(schema, "schema_migrations");
+
+    // The schema and its migrations table are shared by every migration set applied to it, so
+    // are created under their own lock
+    let trans = conn.transaction()?;
+
+    lock_migrations(&trans, schema, "schema_migrations")?;
+
+    if let Some(schema) = schema {
+        trans.batch_execute(&format!(
+            "create schema if not exists {}",
+            quote_ident(schema)
+        ))?;
+    }
+
+    trans.batch_execute(&CREATE_MIGRATIONS_TABLE.replace("{migrations}", &migrations_table))?;
+
+    trans.commit()?;
+
+    let trans = conn.transaction()?;
+
+    lock_migrations(&trans, schema, migration_set)?;
+
+    let applied = trans
+        .query(
+            &format!(
+                "select version from {} where migration_set = $1",
+                migrations_table
+            ),
+            &[&migration_set],
+        )?
+        .iter()
+        .map(|row| row.get(0))
+        .collect::<Vec<i32>>();
+
+    let mut pending = migrations
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .collect::<Vec<&Migration>>();
+
+    pending.sort_by_key(|migration| migration.version);
+
+    debug!(
+        "{} {} migrations already applied, {} pending",
+        applied.len(),
+        migration_set,
+        pending.len()
+    );
+
+    for migration in pending {
+        info!(
+            "Applying {} migration {}: {}",
+            migration_set, migration.version, migration.description
+        );
+
+        let sql = vars
+            .iter()
+            .fold(String::from(migration.sql), |sql, (name, value)| {
+                sql.replace(&format!("{{{}}}", name), value)
+            });
+
+        trans.batch_execute(&sql)?;
+
+        trans.execute(
+            &format!(
+                "insert into {} (migration_set, version, description) values ($1, $2, $3)",
+                migrations_table
+            ),
+            &[&migration_set, &migration.version, &migration.description],
+        )?;
+    }
+
+    trans.commit()?;
+
+    Ok(())
+}
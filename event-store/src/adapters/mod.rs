@@ -2,8 +2,30 @@
 
 mod cache;
 mod emitter;
+mod migrations;
 mod store;
 
-pub use self::cache::{CacheResult, PgCacheAdapter};
-pub use self::emitter::AmqpEmitterAdapter;
-pub use self::store::{PgQuery, PgStoreAdapter, SaveResult, SaveStatus};
+pub use self::cache::{
+    CacheAdapter, CacheResult, MemoryCacheAdapter, PgCacheAdapter, PgCacheTables,
+    RedisCacheAdapter, SnapshotKey, SqliteCacheAdapter,
+};
+pub use self::emitter::{
+    AmqpEmitterAdapter, AmqpMessage, EmitterAdapter, EmitterMessage, MemoryEmitterAdapter,
+    MemoryMessage, MessageStream, PgEmitterAdapter, PgMessage,
+};
+pub(crate) use self::store::decode_events;
+pub use self::store::{
+    DecodePolicy, DecodeReport, EventRecord, EventStream, MemoryQuery, MemoryStoreAdapter,
+    OutboxItem, PgQuery, PgStoreAdapter, PgStoreTables, SaveResult, SaveStatus, SqliteQuery,
+    SqliteStoreAdapter, StoreAdapter,
+};
+
+use crate::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+
+/// Future returned from adapter trait methods
+///
+/// Trait methods can't be `async fn`s, so adapters return a boxed future instead. Implementations
+/// can create one with `Box::pin(async move { ... })`.
+pub type AdapterFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Error>> + Send + 'a>>;
@@ -0,0 +1,98 @@
+use crate::error::Error;
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde_json::Value as JsonValue;
+use std::sync::{Arc, Mutex};
+
+/// What a store adapter should do when an event read from the backing store cannot be decoded
+#[derive(Clone, Debug)]
+pub enum DecodePolicy {
+    /// Return an error from the read, discarding any events that were decoded
+    Fail,
+
+    /// Log the failure and leave the event out of the results
+    Skip,
+
+    /// Leave the event out of the results and record the failure in the given report
+    Collect(DecodeReport),
+}
+
+impl Default for DecodePolicy {
+    fn default() -> Self {
+        DecodePolicy::Fail
+    }
+}
+
+/// A list of decode failures collected by reads using [`DecodePolicy::Collect`]
+///
+/// Clones of a report share the same list, so one clone can be given to an adapter and another
+/// kept to inspect failures after a read.
+#[derive(Clone, Debug, Default)]
+pub struct DecodeReport {
+    failures: Arc<Mutex<Vec<Error>>>,
+}
+
+impl DecodeReport {
+    /// Create a new, empty report
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of failures currently held in the report
+    pub fn len(&self) -> usize {
+        self.failures
+            .lock()
+            .map(|failures| failures.len())
+            .unwrap_or(0)
+    }
+
+    /// Whether the report holds no failures
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove and return all failures recorded so far
+    pub fn take(&self) -> Vec<Error> {
+        self.failures
+            .lock()
+            .map(|mut failures| failures.drain(..).collect())
+            .unwrap_or_default()
+    }
+
+    fn push(&self, failure: Error) {
+        if let Ok(mut failures) = self.failures.lock() {
+            failures.push(failure);
+        }
+    }
+}
+
+/// Decode a list of events read from a backing store as JSON, handling failures according to
+/// `policy`
+pub(crate) fn decode_events<E, I>(rows: I, policy: &DecodePolicy) -> Result<Vec<E>, Error>
+where
+    E: DeserializeOwned,
+    I: IntoIterator<Item = JsonValue>,
+{
+    let mut events = Vec::new();
+
+    for row in rows {
+        match E::deserialize(&row) {
+            Ok(event) => events.push(event),
+            Err(e) => {
+                let err = Error::decode(&row, e);
+
+                match policy {
+                    DecodePolicy::Fail => return Err(err),
+                    DecodePolicy::Skip => warn!("Skipping event: {}", err),
+                    DecodePolicy::Collect(report) => {
+                        warn!("Skipping event: {}", err);
+
+                        report.push(err);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(events)
+}
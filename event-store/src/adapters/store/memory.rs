@@ -0,0 +1,329 @@
+use super::{
+    check_version, expecting, stream_conflict, stream_events, stream_rows, verify_duplicate,
+    DecodePolicy, EventRecord, EventStream, SaveStatus, StoreAdapter, PAGE_SIZE,
+};
+use crate::adapters::AdapterFuture;
+use crate::error::Error;
+use crate::event::Event;
+use crate::event_query::EventQuery;
+use crate::store_query::StoreQuery;
+use chrono::prelude::*;
+use event_store_derive_internals::EventData;
+use event_store_derive_internals::Events;
+use log::{debug, trace};
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+/// Query for events held by a [`MemoryStoreAdapter`]
+///
+/// The filter is called with each event as a JSON object of the form
+/// `{ "id": ..., "data": ..., "context": ... }`, the same shape as rows read from Postgres.
+#[derive(Clone)]
+pub struct MemoryQuery {
+    id: String,
+    filter: Arc<dyn Fn(&JsonValue) -> bool + Send + Sync>,
+}
+
+impl MemoryQuery {
+    /// Create a new query matching all events for which `filter` returns `true`
+    ///
+    /// `id` is used as the cache key for aggregations over this query, so it must be unique to
+    /// the filter and any values it captures.
+    pub fn new<F>(id: &str, filter: F) -> Self
+    where
+        F: Fn(&JsonValue) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            id: id.into(),
+            filter: Arc::new(filter),
+        }
+    }
+
+    /// Create a query that matches every event in the store
+    pub fn all() -> Self {
+        Self::new("all", |_| true)
+    }
+
+    fn matches(&self, event: &JsonValue) -> bool {
+        (self.filter)(event)
+    }
+}
+
+impl fmt::Debug for MemoryQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MemoryQuery").field("id", &self.id).finish()
+    }
+}
+
+impl StoreQuery for MemoryQuery {
+    fn unique_id(&self) -> String {
+        format!("memory:{}", self.id)
+    }
+}
+
+impl From<EventQuery> for MemoryQuery {
+    fn from(query: EventQuery) -> Self {
+        Self::new(&query.unique_id(), move |event| query.matches(event))
+    }
+}
+
+struct MemoryEvent {
+    id: Uuid,
+    time: DateTime<Utc>,
+    stream_id: Option<String>,
+    sequence: Option<i64>,
+    position: i64,
+    value: JsonValue,
+}
+
+/// In-memory store adapter
+///
+/// Events are held in a list in the order they were saved, which is also the order of their
+/// positions. Clones of an adapter share the same list.
+/// Intended for use in tests, where no external services are available.
+#[derive(Clone, Default)]
+pub struct MemoryStoreAdapter {
+    events: Arc<RwLock<Vec<MemoryEvent>>>,
+    decode_policy: DecodePolicy,
+    verify_duplicates: bool,
+}
+
+impl MemoryStoreAdapter {
+    /// Create a new, empty in-memory store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how events that can't be decoded are handled when reading. Defaults to
+    /// [`DecodePolicy::Fail`].
+    pub fn with_decode_policy(self, decode_policy: DecodePolicy) -> Self {
+        Self {
+            decode_policy,
+            ..self
+        }
+    }
+
+    /// Check that events saved with an ID that already exists have the same data and context as
+    /// the stored event
+    ///
+    /// Saving such an event resolves to [`Error::Duplicate`] if they differ, instead of
+    /// `SaveStatus::Duplicate`.
+    pub fn with_duplicate_verification(self) -> Self {
+        Self {
+            verify_duplicates: true,
+            ..self
+        }
+    }
+
+    /// Insert a record, checking that its stream is at `expected_version` first if given
+    fn insert(
+        &self,
+        events: &mut Vec<MemoryEvent>,
+        record: &EventRecord,
+        expected_version: Option<i64>,
+    ) -> Result<SaveStatus, Error> {
+        match events.iter().find(|existing| existing.id == record.id) {
+            Some(existing) if self.verify_duplicates => {
+                verify_duplicate(record, &existing.value["data"], &existing.value["context"])
+            }
+            Some(_) => Ok(SaveStatus::Duplicate),
+            None => {
+                if let (Some(stream_id), Some(expected_version)) =
+                    (&record.stream_id, expected_version)
+                {
+                    check_version(stream_id, expected_version, version(events, stream_id))?;
+                }
+
+                insert_event(events, record)
+            }
+        }
+    }
+
+    fn read_page<F>(&self, after: i64, filter: F) -> Result<Vec<JsonValue>, Error>
+    where
+        F: Fn(&MemoryEvent) -> bool,
+    {
+        let events = self.events.read().map_err(poisoned)?;
+
+        let rows = events
+            .iter()
+            .filter(|event| event.position > after)
+            .filter(|event| filter(event))
+            .take(PAGE_SIZE as usize)
+            .map(|event| event.value.clone())
+            .collect();
+
+        Ok(rows)
+    }
+}
+
+fn version(events: &[MemoryEvent], stream_id: &str) -> i64 {
+    events
+        .iter()
+        .filter(|event| event.stream_id.as_ref().map(|s| s.as_str()) == Some(stream_id))
+        .filter_map(|event| event.sequence)
+        .max()
+        .unwrap_or(0)
+}
+
+fn insert_event(events: &mut Vec<MemoryEvent>, record: &EventRecord) -> Result<SaveStatus, Error> {
+    if let (Some(stream_id), Some(sequence)) = (&record.stream_id, record.sequence) {
+        let taken = events.iter().any(|existing| {
+            existing.stream_id.as_ref() == Some(stream_id) && existing.sequence == Some(sequence)
+        });
+
+        if taken {
+            return Err(stream_conflict(stream_id, sequence));
+        }
+    }
+
+    let position = events.len() as i64 + 1;
+
+    let value = json!({
+        "id": record.id,
+        "data": record.data,
+        "context": record.context,
+        "stream_id": record.stream_id,
+        "sequence": record.sequence,
+        "position": position,
+    });
+
+    events.push(MemoryEvent {
+        id: record.id,
+        time: record.time,
+        stream_id: record.stream_id.clone(),
+        sequence: record.sequence,
+        position,
+        value,
+    });
+
+    Ok(SaveStatus::Ok)
+}
+
+fn poisoned<T>(_: T) -> Error {
+    Error::Connection("Memory store lock poisoned".into())
+}
+
+impl StoreAdapter for MemoryStoreAdapter {
+    type Query = MemoryQuery;
+
+    fn save<'a, ED, S>(&'a self, event: &'a Event<ED, S>) -> AdapterFuture<'a, SaveStatus>
+    where
+        ED: EventData,
+        S: Serialize,
+    {
+        let record = EventRecord::from_event(event);
+
+        Box::pin(async move {
+            self.insert(&mut *self.events.write().map_err(poisoned)?, &record?, None)
+        })
+    }
+
+    fn save_expecting<'a, ED, S>(
+        &'a self,
+        event: &'a Event<ED, S>,
+        expected_version: i64,
+    ) -> AdapterFuture<'a, SaveStatus>
+    where
+        ED: EventData,
+        S: Serialize,
+    {
+        debug!("Save event expecting stream version {}", expected_version);
+
+        let record = expecting(event, expected_version);
+
+        Box::pin(async move {
+            let mut events = self.events.write().map_err(poisoned)?;
+
+            self.insert(&mut events, &record?, Some(expected_version))
+        })
+    }
+
+    fn save_all<'a>(&'a self, records: &'a [EventRecord]) -> AdapterFuture<'a, Vec<SaveStatus>> {
+        Box::pin(async move {
+            debug!("Save batch of {} events", records.len());
+
+            let mut events = self.events.write().map_err(poisoned)?;
+
+            let saved_len = events.len();
+
+            let statuses = records
+                .iter()
+                .map(|record| self.insert(&mut events, record, None))
+                .collect::<Result<Vec<SaveStatus>, Error>>();
+
+            // Roll back any events saved before a failure
+            if statuses.is_err() {
+                events.truncate(saved_len);
+            }
+
+            statuses
+        })
+    }
+
+    fn decode_policy(&self) -> &DecodePolicy {
+        &self.decode_policy
+    }
+
+    fn stream_version<'a>(&'a self, stream_id: &'a str) -> AdapterFuture<'a, i64> {
+        Box::pin(async move {
+            let events = self.events.read().map_err(poisoned)?;
+
+            Ok(version(&events, stream_id))
+        })
+    }
+
+    fn stream<'a, E>(
+        &'a self,
+        query: &'a MemoryQuery,
+        since: Option<DateTime<Utc>>,
+    ) -> EventStream<'a, E>
+    where
+        E: Events + Send + 'a,
+    {
+        debug!("Read query {:?} since {:?}", query, since);
+
+        stream_events(0, self.decode_policy.clone(), move |after| {
+            self.read_page(after, |event| {
+                since.map(|since| event.time >= since).unwrap_or(true)
+                    && query.matches(&event.value)
+            })
+        })
+    }
+
+    fn stream_after<'a, E>(&'a self, query: &'a MemoryQuery, position: i64) -> EventStream<'a, E>
+    where
+        E: Events + Send + 'a,
+    {
+        debug!("Read query {:?} after position {}", query, position);
+
+        stream_events(position, self.decode_policy.clone(), move |after| {
+            self.read_page(after, |event| query.matches(&event.value))
+        })
+    }
+
+    fn stream_events_since<'a>(
+        &'a self,
+        event_namespace: &'a str,
+        event_type: &'a str,
+        since: DateTime<Utc>,
+    ) -> EventStream<'a, JsonValue> {
+        trace!(
+            "Read events of type {}.{} since {}",
+            event_namespace,
+            event_type,
+            since.to_rfc3339()
+        );
+
+        stream_rows(0, move |after| {
+            self.read_page(after, |event| {
+                event.time >= since
+                    && event.value["data"]["event_namespace"] == event_namespace
+                    && event.value["data"]["event_type"] == event_type
+            })
+        })
+    }
+}
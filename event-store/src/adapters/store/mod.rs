@@ -1,3 +1,274 @@
+mod decode;
+mod memory;
+mod outbox;
 mod pg;
+mod record;
+mod sqlite;
+mod stream;
 
-pub use self::pg::{PgQuery, PgStoreAdapter, SaveResult, SaveStatus};
+pub(crate) use self::decode::decode_events;
+pub use self::decode::{DecodePolicy, DecodeReport};
+pub use self::memory::{MemoryQuery, MemoryStoreAdapter};
+pub use self::outbox::OutboxItem;
+pub use self::pg::{PgQuery, PgStoreAdapter, PgStoreTables};
+pub use self::record::EventRecord;
+pub(crate) use self::sqlite::poisoned;
+pub use self::sqlite::{SqliteQuery, SqliteStoreAdapter};
+pub use self::stream::EventStream;
+pub(crate) use self::stream::{stream_events, stream_rows, PAGE_SIZE};
+
+use crate::adapters::AdapterFuture;
+use crate::error::Error;
+use crate::event::Event;
+use crate::internals::forward;
+use crate::store_query::StoreQuery;
+use chrono::prelude::*;
+use event_store_derive_internals::EventData;
+use event_store_derive_internals::Events;
+use futures::Stream;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+/// Save result
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SaveStatus {
+    /// The save was successful
+    Ok,
+
+    /// A duplicate item already exists in the backing store
+    Duplicate,
+}
+
+/// The result of a save operation
+///
+/// If the save did not error but a duplicate was encountered, this should be equal to
+/// `Ok(SaveStatus::Duplicate)`
+pub type SaveResult = Result<SaveStatus, Error>;
+
+/// Create the record for an event being saved with [`StoreAdapter::save_expecting`]
+pub(crate) fn expecting<ED, S>(
+    event: &Event<ED, S>,
+    expected_version: i64,
+) -> Result<EventRecord, Error>
+where
+    ED: EventData,
+    S: Serialize,
+{
+    let record = EventRecord::from_event(event)?;
+
+    if record.stream_id.is_none() {
+        return Err(Error::Concurrency(format!(
+            "Event {} must have a stream ID to be saved with an expected version",
+            event.id
+        )));
+    }
+
+    Ok(EventRecord {
+        sequence: Some(expected_version + 1),
+        ..record
+    })
+}
+
+/// Check that a stream's current version is the version an event is being saved against
+///
+/// Adapters must call this while holding the same lock or transaction that inserts the event, so
+/// that no other writer can save to the stream in between.
+pub(crate) fn check_version(
+    stream_id: &str,
+    expected_version: i64,
+    version: i64,
+) -> Result<(), Error> {
+    if version == expected_version {
+        Ok(())
+    } else {
+        Err(Error::Concurrency(format!(
+            "Stream {} is at version {}, expected version {}",
+            stream_id, version, expected_version
+        )))
+    }
+}
+
+/// Check that a duplicate of `record` found in the store has the same data and context
+///
+/// Resolves to `SaveStatus::Duplicate` if they match, or [`Error::Duplicate`] if they differ.
+pub(crate) fn verify_duplicate(
+    record: &EventRecord,
+    stored_data: &JsonValue,
+    stored_context: &JsonValue,
+) -> Result<SaveStatus, Error> {
+    if &record.data == stored_data && &record.context == stored_context {
+        Ok(SaveStatus::Duplicate)
+    } else {
+        Err(Error::Duplicate(record.id))
+    }
+}
+
+/// Create the error returned when a stream already holds an event at the position being saved to
+pub(crate) fn stream_conflict(stream_id: &str, sequence: i64) -> Error {
+    Error::Concurrency(format!(
+        "Stream {} already has an event with sequence number {}",
+        stream_id, sequence
+    ))
+}
+
+/// A backing store that events are persisted to and read from
+///
+/// [`crate::Store`] is generic over this trait so that backends other than Postgres can be used.
+pub trait StoreAdapter: Clone + Send + Sync + 'static {
+    /// The query type this store understands. [`crate::Aggregator::query`] must return this type
+    /// for aggregators used with this store.
+    type Query: StoreQuery + Send + Sync;
+
+    /// Save an event into the store
+    ///
+    /// Saving an event with an ID that already exists must resolve to `SaveStatus::Duplicate`
+    /// instead of an error. Adapters that can verify duplicates should resolve to
+    /// [`Error::Duplicate`] instead if verification is enabled and the stored event's data or
+    /// context differ. Saving an event with a stream ID and sequence number that already
+    /// exist must resolve to [`Error::Concurrency`].
+    fn save<'a, ED, S>(&'a self, event: &'a Event<ED, S>) -> AdapterFuture<'a, SaveStatus>
+    where
+        ED: EventData,
+        S: Serialize;
+
+    /// Save an event into the store as number `expected_version + 1` in its stream, ignoring
+    /// `event.sequence`
+    ///
+    /// The event must have a stream ID. If the stream's version is not `expected_version` when the
+    /// event is inserted, i.e. another writer has saved to the stream since `expected_version` was
+    /// read, this must resolve to [`Error::Concurrency`]. The check and the insert must be atomic.
+    fn save_expecting<'a, ED, S>(
+        &'a self,
+        event: &'a Event<ED, S>,
+        expected_version: i64,
+    ) -> AdapterFuture<'a, SaveStatus>
+    where
+        ED: EventData,
+        S: Serialize;
+
+    /// Save a batch of events in a single transaction
+    ///
+    /// Either every record is saved or none are. The result holds a status for each record, in
+    /// the same order. Records with an ID that already exists resolve to `SaveStatus::Duplicate`
+    /// without stopping the rest of the batch being saved, but a stream conflict fails the whole
+    /// batch with [`Error::Concurrency`].
+    fn save_all<'a>(&'a self, records: &'a [EventRecord]) -> AdapterFuture<'a, Vec<SaveStatus>>;
+
+    /// How events that can't be decoded are handled when reading from this store
+    fn decode_policy(&self) -> &DecodePolicy;
+
+    /// Get the sequence number of the last event in a stream, or 0 if the stream is empty
+    fn stream_version<'a>(&'a self, stream_id: &'a str) -> AdapterFuture<'a, i64>;
+
+    /// Stream events matching `query`, ordered by position
+    ///
+    /// If `since` is given, only events created at or after that time will be returned. Events
+    /// that can't be decoded into `E` are handled according to the adapter's [`DecodePolicy`].
+    fn stream<'a, E>(
+        &'a self,
+        query: &'a Self::Query,
+        since: Option<DateTime<Utc>>,
+    ) -> EventStream<'a, E>
+    where
+        E: Events + Send + 'a;
+
+    /// Stream events matching `query` with a position greater than `position`, ordered by
+    /// position
+    ///
+    /// Events that can't be decoded are handled according to the adapter's [`DecodePolicy`].
+    /// Adapters must make saved events visible in position order, so that an event can't appear
+    /// with a lower position than one that has already been read.
+    fn stream_after<'a, E>(&'a self, query: &'a Self::Query, position: i64) -> EventStream<'a, E>
+    where
+        E: Events + Send + 'a;
+
+    /// Stream events of a given type starting from a timestamp going forward
+    fn stream_events_since<'a>(
+        &'a self,
+        event_namespace: &'a str,
+        event_type: &'a str,
+        since: DateTime<Utc>,
+    ) -> EventStream<'a, JsonValue>;
+
+    /// Read a list of events matching `query`, ordered by position
+    ///
+    /// Collects the results of [`StoreAdapter::stream`], so holds every matching event in memory.
+    fn read<'a, E>(
+        &'a self,
+        query: &'a Self::Query,
+        since: Option<DateTime<Utc>>,
+    ) -> AdapterFuture<'a, Vec<E>>
+    where
+        E: Events + Send + 'a,
+    {
+        let events = self.stream(query, since);
+
+        Box::pin(async move { await!(forward(Box::new(events.collect()))) })
+    }
+
+    /// Read a list of events matching `query` with a position greater than `position`, ordered by
+    /// position
+    ///
+    /// Collects the results of [`StoreAdapter::stream_after`], so holds every matching event in
+    /// memory.
+    fn read_after<'a, E>(
+        &'a self,
+        query: &'a Self::Query,
+        position: i64,
+    ) -> AdapterFuture<'a, Vec<E>>
+    where
+        E: Events + Send + 'a,
+    {
+        let events = self.stream_after(query, position);
+
+        Box::pin(async move { await!(forward(Box::new(events.collect()))) })
+    }
+
+    /// Fetch events of a given type starting from a timestamp going forward
+    ///
+    /// Collects the results of [`StoreAdapter::stream_events_since`].
+    fn read_events_since<'a>(
+        &'a self,
+        event_namespace: &'a str,
+        event_type: &'a str,
+        since: DateTime<Utc>,
+    ) -> AdapterFuture<'a, Vec<JsonValue>> {
+        let events = self.stream_events_since(event_namespace, event_type, since);
+
+        Box::pin(async move { await!(forward(events.collect())) })
+    }
+
+    /// Whether saved events are written to an outbox in the same transaction, to be published by
+    /// the outbox relay instead of being emitted directly
+    ///
+    /// Stores that don't support an outbox can leave this and the other outbox methods as their
+    /// default no-op implementations. Of the built in adapters, only [`PgStoreAdapter`] supports an
+    /// outbox.
+    fn uses_outbox(&self) -> bool {
+        false
+    }
+
+    /// Claim up to `limit` outbox items that are due to be published, oldest first
+    ///
+    /// Claimed items must not be returned again, to this or any other relay, until some time
+    /// has passed without them being marked as sent or failed.
+    fn pending_outbox<'a>(&'a self, _limit: i64) -> AdapterFuture<'a, Vec<OutboxItem>> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+
+    /// Mark an outbox item as published
+    fn mark_outbox_sent<'a>(&'a self, _event_id: Uuid) -> AdapterFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Record a failed attempt to publish an outbox item, delaying the next attempt until
+    /// `retry_at`
+    fn mark_outbox_failed<'a>(
+        &'a self,
+        _event_id: Uuid,
+        _retry_at: DateTime<Utc>,
+    ) -> AdapterFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
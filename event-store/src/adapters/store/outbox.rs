@@ -0,0 +1,18 @@
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+/// An event waiting in a store's outbox to be published by the outbox relay
+#[derive(Debug, Clone)]
+pub struct OutboxItem {
+    /// ID of the saved event
+    pub event_id: Uuid,
+
+    /// Routing key to publish the event with, of the form `event_namespace.event_type`
+    pub routing_key: String,
+
+    /// The event, serialized to JSON
+    pub payload: JsonValue,
+
+    /// Number of failed attempts to publish this item so far
+    pub attempts: i32,
+}
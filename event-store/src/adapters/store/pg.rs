@@ -1,39 +1,284 @@
+use super::{
+    check_version, expecting, stream_conflict, stream_events, stream_rows, verify_duplicate,
+    DecodePolicy, EventRecord, EventStream, OutboxItem, SaveStatus, StoreAdapter, PAGE_SIZE,
+};
+use crate::adapters::migrations::{
+    migrate, qualify, quote_ident, quote_literal, schema_literal, Migration,
+};
+use crate::adapters::AdapterFuture;
+use crate::error::Error;
 use crate::event::Event;
+use crate::event_query::{field_path, subject_field_path, EventQuery};
 use crate::store_query::StoreQuery;
 use chrono::prelude::*;
 use event_store_derive_internals::EventData;
 use event_store_derive_internals::Events;
-use fallible_iterator::FallibleIterator;
 use log::{debug, trace};
 use postgres::error::UNIQUE_VIOLATION;
+use postgres::rows::Row;
+use postgres::GenericConnection;
 use r2d2::Pool;
 use r2d2_postgres::postgres::types::ToSql;
 use r2d2_postgres::PostgresConnectionManager;
-use serde_json::{from_value, json, to_value, Value as JsonValue};
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
 use sha2::{Digest, Sha256};
-use std::io;
 use uuid::Uuid;
 
-const INIT_QUERIES: &'static str = r#"
--- Create UUID extension just in case
-create extension if not exists "uuid-ossp";
+/// Advisory lock class for saves to an events table, held until the saving transaction ends
+///
+/// Positions are allocated from a sequence when an event is inserted but only become visible when
+/// its transaction commits. Serializing saves makes events visible in position order, so a reader
+/// resuming after a position can't miss an event committed later with a lower one. It also stops
+/// expected version checks and inserts to the same stream from interleaving.
+const SAVE_LOCK_CLASS: i32 = 0x7361_7665;
+
+/// Seconds an outbox item claimed by a relay is hidden from other relays, after which it's
+/// retried if it still hasn't been marked as sent or failed
+const OUTBOX_LEASE_SECS: f64 = 60.0;
+
+/// Schema changes for the events tables, applied in order by [`PgStoreAdapter::new`]
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Create events table",
+        sql: r#"
+            -- Create UUID extension just in case
+            create extension if not exists "uuid-ossp";
+
+            create table if not exists {events}(
+                id uuid default uuid_generate_v4() primary key,
+                data jsonb not null,
+                context jsonb default '{}'
+            );
+
+            -- Add index on time to speed up filtering
+            create index if not exists {time_index} on {events} ((context->>'time') asc);
+
+            -- Create index to speed up queries by type
+            create index if not exists {type_legacy_index} on {events} ((data->>'type') nulls last);
+        "#,
+    },
+    Migration {
+        version: 2,
+        description: "Add stream ID and sequence number",
+        sql: r#"
+            alter table {events} add column if not exists stream_id varchar(255);
+            alter table {events} add column if not exists sequence bigint;
+
+            -- Prevent two events from taking the same position in a stream
+            create unique index if not exists {stream_sequence_index} on {events} (stream_id, sequence);
+        "#,
+    },
+    Migration {
+        version: 3,
+        description: "Add global position",
+        sql: r#"
+            -- Number any existing events in time order
+            do $$
+            begin
+                if not exists (
+                    select 1 from information_schema.columns
+                    where table_schema = {schema} and table_name = {events_name} and column_name = 'position'
+                ) then
+                    create sequence {position_seq};
+
+                    alter table {events} add column position bigint;
+
+                    update {events} as events set position = numbered.position
+                    from (
+                        select id, row_number() over (
+                            order by (context->>'time')::timestamp with time zone asc, id asc
+                        ) as position
+                        from {events}
+                    ) as numbered
+                    where events.id = numbered.id;
+
+                    perform setval({position_seq_name}, coalesce((select max(position) from {events}), 0) + 1, false);
+
+                    alter table {events} alter column position set default nextval({position_seq_name});
+                    alter table {events} alter column position set not null;
+                    alter sequence {position_seq} owned by {events}.position;
+                end if;
+            end
+            $$;
+
+            create unique index if not exists {position_index} on {events} (position asc);
+        "#,
+    },
+    Migration {
+        version: 4,
+        description: "Create outbox table",
+        sql: r#"
+            create table if not exists {outbox}(
+                id bigserial primary key,
+                event_id uuid not null unique references {events} (id),
+                routing_key text not null,
+                payload jsonb not null,
+                attempts integer not null default 0,
+                next_attempt_at timestamp with time zone not null default now(),
+                sent_at timestamp with time zone
+            );
+
+            create index if not exists {outbox_pending_index} on {outbox} (next_attempt_at asc) where sent_at is null;
+        "#,
+    },
+    Migration {
+        version: 5,
+        description: "Promote event namespace and type to columns",
+        sql: r#"
+            -- Fill the new columns in from the data of existing events
+            do $$
+            begin
+                if not exists (
+                    select 1 from information_schema.columns
+                    where table_schema = {schema} and table_name = {events_name} and column_name = 'event_namespace'
+                ) then
+                    alter table {events} add column event_namespace varchar(255);
+                    alter table {events} add column event_type varchar(255);
+
+                    update {events} set
+                        event_namespace = data->>'event_namespace',
+                        event_type = data->>'event_type';
+                end if;
+            end
+            $$;
+
+            -- Replace the old index on context fields, which events never have
+            drop index if exists {legacy_namespace_index};
+            create index if not exists {namespace_type_index} on {events} (event_namespace, event_type);
+        "#,
+    },
+];
+
+/// Placeholder for the events table in [`PgQuery`] query strings
+const EVENTS_TABLE_PLACEHOLDER: &str = "{events}";
+
+/// Names of the Postgres schema and tables a [`PgStoreAdapter`] uses
+///
+/// By default tables are created in the connection's current schema and named `events` and
+/// `events_outbox`. Stores for different contexts can share a database by using a different
+/// schema or table names for each.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgStoreTables {
+    schema: Option<String>,
+    events: String,
+    outbox: String,
+}
 
--- Create events table if it doesn't already exist
-create table if not exists events(
-    id uuid default uuid_generate_v4() primary key,
-    data jsonb not null,
-    context jsonb default '{}'
-);
+impl Default for PgStoreTables {
+    fn default() -> Self {
+        Self {
+            schema: None,
+            events: "events".into(),
+            outbox: "events_outbox".into(),
+        }
+    }
+}
 
--- Add index on sequence number and time to speed up ordering
-create index if not exists counter_time on events ((context->>'time') asc);
+impl PgStoreTables {
+    /// Use the default table names in the connection's current schema
+    pub fn new() -> Self {
+        Self::default()
+    }
 
--- Create index to speed up queries by type
-create index if not exists event_type_legacy on events ((data->>'type') nulls last);
-create index if not exists event_namespace_and_type on events ((context->>'event_namespace') nulls last, (context->>'event_type') nulls last);
-"#;
+    /// Create tables in the given schema, creating the schema if it doesn't exist
+    pub fn with_schema(self, schema: &str) -> Self {
+        Self {
+            schema: Some(schema.into()),
+            ..self
+        }
+    }
+
+    /// Set the name of the events table
+    pub fn with_events_table(self, events: &str) -> Self {
+        Self {
+            events: events.into(),
+            ..self
+        }
+    }
+
+    /// Set the name of the outbox table
+    pub fn with_outbox_table(self, outbox: &str) -> Self {
+        Self {
+            outbox: outbox.into(),
+            ..self
+        }
+    }
+
+    /// The quoted, schema-qualified name of the events table
+    pub(crate) fn events(&self) -> String {
+        qualify(self.schema.as_ref().map(|s| s.as_str()), &self.events)
+    }
+
+    /// The quoted, schema-qualified name of the outbox table
+    pub(crate) fn outbox(&self) -> String {
+        qualify(self.schema.as_ref().map(|s| s.as_str()), &self.outbox)
+    }
+
+    /// The name of the unique index on `(stream_id, sequence)`
+    fn stream_sequence_index(&self) -> String {
+        format!("{}_stream_sequence", self.events)
+    }
+
+    fn migration_vars(&self) -> Vec<(&'static str, String)> {
+        let schema = self.schema.as_ref().map(|s| s.as_str());
+        let position_seq = qualify(schema, &format!("{}_position_seq", self.events));
+
+        // Indexes created before table names were configurable keep their original names
+        let (time_index, type_legacy_index, legacy_namespace_index) = if self.events == "events" {
+            (
+                "counter_time".into(),
+                "event_type_legacy".into(),
+                "event_namespace_and_type".into(),
+            )
+        } else {
+            (
+                format!("{}_time", self.events),
+                format!("{}_type_legacy", self.events),
+                format!("{}_namespace_and_type", self.events),
+            )
+        };
+
+        vec![
+            ("events", self.events()),
+            ("events_name", quote_literal(&self.events)),
+            ("outbox", self.outbox()),
+            ("schema", schema_literal(schema)),
+            ("position_seq_name", quote_literal(&position_seq)),
+            ("position_seq", position_seq),
+            ("time_index", quote_ident(&time_index)),
+            ("type_legacy_index", quote_ident(&type_legacy_index)),
+            (
+                "stream_sequence_index",
+                quote_ident(&self.stream_sequence_index()),
+            ),
+            (
+                "position_index",
+                quote_ident(&format!("{}_position", self.events)),
+            ),
+            (
+                "namespace_type_index",
+                quote_ident(&format!("{}_namespace_type", self.events)),
+            ),
+            (
+                "outbox_pending_index",
+                quote_ident(&format!("{}_pending", self.outbox)),
+            ),
+            (
+                "legacy_namespace_index",
+                qualify(schema, &legacy_namespace_index),
+            ),
+        ]
+    }
+}
 
 /// Representation of a Postgres query and args
+///
+/// Queries must select the `position` column, e.g. `select * from {events} where ...`, as results
+/// are ordered by it. `{events}` is replaced with the adapter's events table, configured with
+/// [`PgStoreTables`]. Filter on the indexed `event_namespace` and `event_type` columns instead of
+/// the same fields in `data`.
 #[derive(Debug)]
 pub struct PgQuery {
     /// Query string with placeholders
@@ -63,168 +308,487 @@ impl StoreQuery for PgQuery {
     }
 }
 
-fn generate_query(initial_query: &PgQuery, since: Option<DateTime<Utc>>) -> String {
-    if let Some(timestamp) = since {
-        String::from(format!(
-            "select * from ({}) as events where (events.context->>'time')::timestamp with time zone >= '{}' order by (events.context->>'time')::timestamp with time zone asc",
-            initial_query.query, timestamp,
-        ))
-    } else {
-        String::from(format!(
-            "select * from ({}) as events order by (events.context->>'time')::timestamp with time zone asc",
-            initial_query.query
-        ))
+impl From<EventQuery> for PgQuery {
+    fn from(query: EventQuery) -> Self {
+        let mut conditions = Vec::new();
+        let mut args: Vec<Box<ToSql + Send + Sync>> = Vec::new();
+
+        if !query.event_types.is_empty() {
+            let types = query
+                .event_types
+                .into_iter()
+                .map(|(event_namespace, event_type)| {
+                    format!(
+                        "(event_namespace = {} and event_type = {})",
+                        push_arg(&mut args, event_namespace),
+                        push_arg(&mut args, event_type)
+                    )
+                })
+                .collect::<Vec<String>>();
+
+            conditions.push(format!("({})", types.join(" or ")));
+        }
+
+        if !query.namespaces.is_empty() {
+            let namespaces = query.namespaces.into_iter().collect::<Vec<String>>();
+
+            conditions.push(format!(
+                "event_namespace = any({})",
+                push_arg(&mut args, namespaces)
+            ));
+        }
+
+        if let Some(stream_id) = query.stream_id {
+            conditions.push(format!("stream_id = {}", push_arg(&mut args, stream_id)));
+        }
+
+        for (path, value) in query.fields {
+            conditions.push(format!(
+                "data #> {} = {}",
+                push_arg(&mut args, field_path(&path)),
+                push_arg(&mut args, value)
+            ));
+        }
+
+        for (path, value) in query.subject_fields {
+            conditions.push(format!(
+                "context #> {} = {}",
+                push_arg(&mut args, subject_field_path(&path)),
+                push_arg(&mut args, value)
+            ));
+        }
+
+        if let Some(since) = query.since {
+            conditions.push(format!(
+                "(context->>'time')::timestamp with time zone >= {}",
+                push_arg(&mut args, since)
+            ));
+        }
+
+        if let Some(before) = query.before {
+            conditions.push(format!(
+                "(context->>'time')::timestamp with time zone < {}",
+                push_arg(&mut args, before)
+            ));
+        }
+
+        if let Some(position) = query.after_position {
+            conditions.push(format!("position > {}", push_arg(&mut args, position)));
+        }
+
+        if let Some(position) = query.up_to_position {
+            conditions.push(format!("position <= {}", push_arg(&mut args, position)));
+        }
+
+        let query_string = if conditions.is_empty() {
+            format!("select * from {}", EVENTS_TABLE_PLACEHOLDER)
+        } else {
+            format!(
+                "select * from {} where {}",
+                EVENTS_TABLE_PLACEHOLDER,
+                conditions.join(" and ")
+            )
+        };
+
+        Self::new(&query_string, args)
     }
 }
 
-/// Save result
-pub enum SaveStatus {
-    /// The save was successful
-    Ok,
+/// Add an argument to a query being built, returning its placeholder
+fn push_arg<T>(args: &mut Vec<Box<ToSql + Send + Sync>>, arg: T) -> String
+where
+    T: ToSql + Send + Sync + 'static,
+{
+    args.push(Box::new(arg));
 
-    /// A duplicate item already exists in the backing store
-    Duplicate,
+    format!("${}", args.len())
 }
 
-/// The result of a save operation
-///
-/// If the save did not error but a duplicate was encountered, this should be equal to
-/// `Ok(SaveStatus::Ok)`
-pub type SaveResult = Result<SaveStatus, io::Error>;
+fn generate_query(
+    initial_query: &PgQuery,
+    events_table: &str,
+    since: Option<DateTime<Utc>>,
+    after: i64,
+) -> String {
+    let since_filter = since
+        .map(|timestamp| {
+            format!(
+                " and (events.context->>'time')::timestamp with time zone >= '{}'",
+                timestamp
+            )
+        })
+        .unwrap_or_default();
+
+    format!(
+        "select * from ({}) as events where events.position > {}{} order by events.position asc limit {}",
+        initial_query.query.replace(EVENTS_TABLE_PLACEHOLDER, events_table),
+        after,
+        since_filter,
+        PAGE_SIZE,
+    )
+}
+
+fn row_to_json(row: Row) -> JsonValue {
+    let id: Uuid = row.get("id");
+    let data_json: JsonValue = row.get("data");
+    let context_json: JsonValue = row.get("context");
+
+    // Queries may not select the stream columns
+    let stream_id: Option<String> = row
+        .get_opt("stream_id")
+        .and_then(|v| v.ok())
+        .and_then(|v| v);
+    let sequence: Option<i64> = row.get_opt("sequence").and_then(|v| v.ok()).and_then(|v| v);
+    let position: Option<i64> = row.get_opt("position").and_then(|v| v.ok());
+
+    json!({
+        "id": id,
+        "data": data_json,
+        "context": context_json,
+        "stream_id": stream_id,
+        "sequence": sequence,
+        "position": position,
+    })
+}
 
 /// Postgres-backed store adapter
+///
+/// Saves to the same events table are serialized by an advisory lock held until each save's
+/// transaction commits, so events become visible in position order.
 #[derive(Clone)]
 pub struct PgStoreAdapter {
     conn: Pool<PostgresConnectionManager>,
+    tables: PgStoreTables,
+    decode_policy: DecodePolicy,
+    outbox: bool,
+    verify_duplicates: bool,
 }
 
 impl PgStoreAdapter {
     /// Create a new Postgres store
     ///
-    /// This will attempt to create the events table and indexes if they do not already exist
-    pub async fn new(conn: Pool<PostgresConnectionManager>) -> Result<Self, io::Error> {
-        conn.get()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?
-            .batch_execute(INIT_QUERIES)?;
+    /// This applies any schema migrations for the events tables that haven't been applied yet
+    pub async fn new(conn: Pool<PostgresConnectionManager>) -> Result<Self, Error> {
+        await!(Self::new_with_tables(conn, PgStoreTables::default()))
+    }
+
+    /// Create a new Postgres store using the given schema and table names
+    ///
+    /// Migrations are applied to, and recorded in, the configured schema.
+    pub async fn new_with_tables(
+        conn: Pool<PostgresConnectionManager>,
+        tables: PgStoreTables,
+    ) -> Result<Self, Error> {
+        migrate(
+            &*conn.get()?,
+            tables.schema.as_ref().map(|s| s.as_str()),
+            &tables.events,
+            MIGRATIONS,
+            &tables.migration_vars(),
+        )?;
+
+        Ok(Self {
+            conn,
+            tables,
+            decode_policy: DecodePolicy::default(),
+            outbox: false,
+            verify_duplicates: false,
+        })
+    }
+
+    /// Set how events that can't be decoded are handled when reading. Defaults to
+    /// [`DecodePolicy::Fail`].
+    pub fn with_decode_policy(self, decode_policy: DecodePolicy) -> Self {
+        Self {
+            decode_policy,
+            ..self
+        }
+    }
+
+    /// Write saved events to an outbox table in the same transaction as the event itself
+    ///
+    /// Stores using this adapter won't emit events when they're saved. Instead, call
+    /// [`crate::SubscribableStore::start_outbox_relay`] to publish events from the outbox, so an
+    /// event can't be saved without eventually being published.
+    pub fn with_outbox(self) -> Self {
+        Self {
+            outbox: true,
+            ..self
+        }
+    }
 
-        Ok(Self { conn })
+    /// Check that events saved with an ID that already exists have the same data and context as
+    /// the stored event
+    ///
+    /// Saving such an event resolves to [`Error::Duplicate`] if they differ, instead of
+    /// `SaveStatus::Duplicate`.
+    pub fn with_duplicate_verification(self) -> Self {
+        Self {
+            verify_duplicates: true,
+            ..self
+        }
     }
 
-    /// Save an event into PG
-    pub fn save<'a, ED>(&'a self, event: &'a Event<ED>) -> SaveResult
+    fn save_record(
+        &self,
+        record: &EventRecord,
+        expected_version: Option<i64>,
+    ) -> Result<SaveStatus, Error> {
+        let conn = self.conn.get()?;
+
+        let trans = conn.transaction()?;
+
+        self.lock_events(&trans)?;
+
+        let status = self.insert(&trans, record, expected_version)?;
+
+        trans.commit()?;
+
+        Ok(status)
+    }
+
+    fn read_page(
+        &self,
+        query: &PgQuery,
+        since: Option<DateTime<Utc>>,
+        after: i64,
+    ) -> Result<Vec<JsonValue>, Error> {
+        let query_string = generate_query(query, &self.tables.events(), since, after);
+
+        debug!("Read query {}", query_string);
+
+        let params: Vec<&ToSql> = query.args.iter().map(|arg| &**arg as &ToSql).collect();
+
+        let rows = self.conn.get()?.query(&query_string, &params)?;
+
+        Ok(rows.iter().map(row_to_json).collect())
+    }
+
+    /// Take the save lock for the events table, released when the transaction ends
+    fn lock_events(&self, trans: &GenericConnection) -> Result<(), Error> {
+        trans.execute(
+            "select pg_advisory_xact_lock($1, hashtext($2))",
+            &[&SAVE_LOCK_CLASS, &self.tables.events()],
+        )?;
+
+        Ok(())
+    }
+
+    /// Insert a record, checking that its stream is at `expected_version` first if given
+    ///
+    /// Must be called in a transaction holding the lock taken by [`PgStoreAdapter::lock_events`].
+    fn insert(
+        &self,
+        conn: &GenericConnection,
+        record: &EventRecord,
+        expected_version: Option<i64>,
+    ) -> Result<SaveStatus, Error> {
+        let events = self.tables.events();
+
+        if let (Some(stream_id), Some(expected_version)) = (&record.stream_id, expected_version) {
+            let rows = conn.query(
+                &format!(
+                    r#"select
+                        exists(select 1 from {events} where id = $1),
+                        (select coalesce(max(sequence), 0) from {events} where stream_id = $2)"#,
+                    events = events
+                ),
+                &[&record.id, stream_id],
+            )?;
+
+            let row = rows.get(0);
+            let exists: bool = row.get(0);
+
+            // Duplicates resolve the same way as without an expected version
+            if !exists {
+                check_version(stream_id, expected_version, row.get(1))?;
+            }
+        }
+
+        let status = insert_event(conn, &self.tables, record)?;
+
+        if status == SaveStatus::Duplicate && self.verify_duplicates {
+            let rows = conn.query(
+                &format!("select data, context from {} where id = $1 limit 1", events),
+                &[&record.id],
+            )?;
+
+            // The duplicate may have been deleted in the meantime
+            if rows.len() == 1 {
+                let row = rows.get(0);
+
+                return verify_duplicate(record, &row.get(0), &row.get(1));
+            }
+        }
+
+        if status == SaveStatus::Ok && self.outbox {
+            let payload = record.to_json();
+
+            let routing_key = record
+                .event_namespace_and_type()
+                .ok_or_else(|| Error::decode(&payload, "Event data has no namespace or type"))?;
+
+            conn.execute(
+                &format!(
+                    "insert into {} (event_id, routing_key, payload) values ($1, $2, $3)",
+                    self.tables.outbox()
+                ),
+                &[&record.id, &routing_key, &payload],
+            )?;
+        }
+
+        Ok(status)
+    }
+}
+
+fn insert_event(
+    conn: &GenericConnection,
+    tables: &PgStoreTables,
+    record: &EventRecord,
+) -> Result<SaveStatus, Error> {
+    debug!(
+        "Insert event {} ({})",
+        record
+            .event_namespace_and_type()
+            .unwrap_or_else(|| String::from("(unknown type)")),
+        record.id
+    );
+
+    let result = conn.execute(
+        &format!(
+            r#"insert into {} (id, data, context, stream_id, sequence, event_namespace, event_type)
+                values ($1, $2, $3, $4, $5, $6, $7)
+                on conflict (id) do nothing"#,
+            tables.events()
+        ),
+        &[
+            &record.id,
+            &record.data,
+            &record.context,
+            &record.stream_id,
+            &record.sequence,
+            &record.event_namespace(),
+            &record.event_type(),
+        ],
+    );
+
+    match result {
+        Ok(0) => Ok(SaveStatus::Duplicate),
+        Ok(_) => Ok(SaveStatus::Ok),
+        Err(ref err) if err.code() == Some(&UNIQUE_VIOLATION) => {
+            let constraint = err.as_db().and_then(|db_err| db_err.constraint.as_ref());
+
+            match (constraint, &record.stream_id, record.sequence) {
+                (Some(constraint), Some(stream_id), Some(sequence))
+                    if *constraint == tables.stream_sequence_index() =>
+                {
+                    Err(stream_conflict(stream_id, sequence))
+                }
+                // Duplicate IDs are skipped by `on conflict (id) do nothing`, so any other
+                // violation is an error
+                _ => Err(Error::Database(format!("Could not save event: {}", err))),
+            }
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+impl StoreAdapter for PgStoreAdapter {
+    type Query = PgQuery;
+
+    fn save<'a, ED, S>(&'a self, event: &'a Event<ED, S>) -> AdapterFuture<'a, SaveStatus>
     where
         ED: EventData,
+        S: Serialize,
     {
-        debug!(
-            "Insert event {}.{}",
-            ED::event_namespace(),
-            ED::event_type()
-        );
+        let record = EventRecord::from_event(event);
 
-        self.conn
-            .get()
-            .unwrap()
-            .execute(
-                "insert into events (id, data, context) values ($1, $2, $3)",
-                &[
-                    &event.id,
-                    &to_value(&event.data).expect("Unable to convert event data to value"),
-                    &to_value(&event.context).expect("Cannot convert event context"),
-                ],
-            )
-            .map(|_| Ok(SaveStatus::Ok))
-            .unwrap_or_else(|err| {
-                let is_duplicate_error = err.code().unwrap() == &UNIQUE_VIOLATION;
-
-                if is_duplicate_error {
-                    Ok(SaveStatus::Duplicate)
-                } else {
-                    Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("Could not save event: {}", err),
-                    ))
-                }
-            })
+        Box::pin(async move { self.save_record(&record?, None) })
     }
 
-    /// Read a list of events
-    pub async fn read<'a, E>(
+    fn save_expecting<'a, ED, S>(
         &'a self,
-        query: &'a PgQuery,
-        since: Option<DateTime<Utc>>,
-    ) -> Result<Vec<E>, io::Error>
+        event: &'a Event<ED, S>,
+        expected_version: i64,
+    ) -> AdapterFuture<'a, SaveStatus>
     where
-        E: Events,
+        ED: EventData,
+        S: Serialize,
     {
-        let query_string = generate_query(&query, since);
+        debug!("Save event expecting stream version {}", expected_version);
 
-        debug!("Read query {}", query_string);
+        let record = expecting(event, expected_version);
 
-        let conn = self.conn.get().unwrap();
+        Box::pin(async move { self.save_record(&record?, Some(expected_version)) })
+    }
 
-        let trans = conn
-            .transaction()
-            .expect("Unable to initialise transaction");
+    fn save_all<'a>(&'a self, records: &'a [EventRecord]) -> AdapterFuture<'a, Vec<SaveStatus>> {
+        Box::pin(async move {
+            debug!("Save batch of {} events", records.len());
 
-        let stmt = trans
-            .prepare(&query_string)
-            .expect("Unable to prepare read statement");
+            let conn = self.conn.get()?;
 
-        let mut params: Vec<&ToSql> = Vec::new();
+            let trans = conn.transaction()?;
 
-        for (i, _arg) in query.args.iter().enumerate() {
-            params.push(&*query.args[i]);
-        }
+            self.lock_events(&trans)?;
+
+            let statuses = records
+                .iter()
+                .map(|record| self.insert(&trans, record, None))
+                .collect::<Result<Vec<SaveStatus>, Error>>()?;
 
-        let results = stmt
-            .lazy_query(&trans, &params, 1000)
-            .unwrap()
-            .map(|row| {
-                let id: Uuid = row.get("id");
-                let data_json: JsonValue = row.get("data");
-                let context_json: JsonValue = row.get("context");
+            trans.commit()?;
 
-                let thing = json!({
-                    "id": id,
-                    "data": data_json,
-                    "context": context_json,
-                });
+            Ok(statuses)
+        })
+    }
 
-                let evt: E = from_value(thing).expect("Could not decode row");
+    fn decode_policy(&self) -> &DecodePolicy {
+        &self.decode_policy
+    }
 
-                evt
-            })
-            .collect()
-            .expect("Failed to collect results");
+    fn stream_version<'a>(&'a self, stream_id: &'a str) -> AdapterFuture<'a, i64> {
+        Box::pin(async move {
+            let rows = self.conn.get()?.query(
+                &format!(
+                    "select coalesce(max(sequence), 0) from {} where stream_id = $1",
+                    self.tables.events()
+                ),
+                &[&stream_id],
+            )?;
+
+            Ok(rows.get(0).get(0))
+        })
+    }
 
-        trans.finish().expect("Could not finish transaction");
+    fn stream<'a, E>(
+        &'a self,
+        query: &'a PgQuery,
+        since: Option<DateTime<Utc>>,
+    ) -> EventStream<'a, E>
+    where
+        E: Events + Send + 'a,
+    {
+        stream_events(0, self.decode_policy.clone(), move |after| {
+            self.read_page(query, since, after)
+        })
+    }
 
-        Ok(results)
+    fn stream_after<'a, E>(&'a self, query: &'a PgQuery, position: i64) -> EventStream<'a, E>
+    where
+        E: Events + Send + 'a,
+    {
+        stream_events(position, self.decode_policy.clone(), move |after| {
+            self.read_page(query, None, after)
+        })
     }
 
-    /// Fetch events of a given type starting from a timestamp going forward
-    pub async fn read_events_since<'a>(
+    fn stream_events_since<'a>(
         &'a self,
         event_namespace: &'a str,
         event_type: &'a str,
         since: DateTime<Utc>,
-    ) -> Result<Vec<JsonValue>, io::Error> {
-        let query_string = r#"select * from events
-            where data->>'event_namespace' = $1
-            and data->>'event_type' = $2
-            and context->>'time' >= $3
-            order by (context->>'time')::timestamp with time zone asc"#;
-
-        let conn = self.conn.get().unwrap();
-
-        let trans = conn
-            .transaction()
-            .expect("Unable to initialise transaction");
-
-        let stmt = trans
-            .prepare(&query_string)
-            .expect("Unable to prepare read statement");
-
+    ) -> EventStream<'a, JsonValue> {
         trace!(
             "Read events of type {}.{} since {}",
             event_namespace,
@@ -232,29 +796,100 @@ impl PgStoreAdapter {
             since.to_rfc3339()
         );
 
-        let results = stmt
-            .lazy_query(
-                &trans,
-                &[&event_namespace, &event_type, &since.to_rfc3339()],
-                1000,
-            )
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?
-            .map(|row| {
-                let id: Uuid = row.get("id");
-                let data_json: JsonValue = row.get("data");
-                let context_json: JsonValue = row.get("context");
-
-                json!({
-                    "id": id,
-                    "data": data_json,
-                    "context": context_json,
+        let query_string = format!(
+            r#"select * from {}
+                where event_namespace = $1
+                and event_type = $2
+                and (context->>'time')::timestamp with time zone >= $3
+                and position > $4
+                order by position asc
+                limit $5"#,
+            self.tables.events()
+        );
+
+        stream_rows(0, move |after| {
+            let rows = self.conn.get()?.query(
+                &query_string,
+                &[&event_namespace, &event_type, &since, &after, &PAGE_SIZE],
+            )?;
+
+            Ok(rows.iter().map(row_to_json).collect())
+        })
+    }
+
+    fn uses_outbox(&self) -> bool {
+        self.outbox
+    }
+
+    fn pending_outbox<'a>(&'a self, limit: i64) -> AdapterFuture<'a, Vec<OutboxItem>> {
+        Box::pin(async move {
+            // Claimed items are hidden from other relays until they're marked or the lease ends
+            let rows = self.conn.get()?.query(
+                &format!(
+                    r#"with claimed as (
+                            update {outbox}
+                            set next_attempt_at = now() + $2 * interval '1 second'
+                            where id in (
+                                select id from {outbox}
+                                where sent_at is null and next_attempt_at <= now()
+                                order by id asc
+                                limit $1
+                                for update skip locked
+                            )
+                            returning id, event_id, routing_key, payload, attempts
+                        )
+                        select event_id, routing_key, payload, attempts from claimed
+                        order by id asc"#,
+                    outbox = self.tables.outbox()
+                ),
+                &[&limit, &OUTBOX_LEASE_SECS],
+            )?;
+
+            let items = rows
+                .iter()
+                .map(|row| OutboxItem {
+                    event_id: row.get(0),
+                    routing_key: row.get(1),
+                    payload: row.get(2),
+                    attempts: row.get(3),
                 })
-            })
-            .collect()
-            .expect("Failed to collect results");
+                .collect();
+
+            Ok(items)
+        })
+    }
 
-        trans.finish().expect("Could not finish transaction");
+    fn mark_outbox_sent<'a>(&'a self, event_id: Uuid) -> AdapterFuture<'a, ()> {
+        Box::pin(async move {
+            self.conn.get()?.execute(
+                &format!(
+                    "update {} set sent_at = now() where event_id = $1",
+                    self.tables.outbox()
+                ),
+                &[&event_id],
+            )?;
+
+            Ok(())
+        })
+    }
 
-        Ok(results)
+    fn mark_outbox_failed<'a>(
+        &'a self,
+        event_id: Uuid,
+        retry_at: DateTime<Utc>,
+    ) -> AdapterFuture<'a, ()> {
+        Box::pin(async move {
+            self.conn.get()?.execute(
+                &format!(
+                    r#"update {}
+                        set attempts = attempts + 1, next_attempt_at = $2
+                        where event_id = $1"#,
+                    self.tables.outbox()
+                ),
+                &[&event_id, &retry_at],
+            )?;
+
+            Ok(())
+        })
     }
 }
@@ -0,0 +1,125 @@
+use crate::error::Error;
+use crate::event::Event;
+use chrono::prelude::*;
+use event_store_derive_internals::EventData;
+use event_store_derive_internals::Events;
+use serde::Serialize;
+use serde_derive::Deserialize;
+use serde_json::{from_value, json, to_value, Value as JsonValue};
+use uuid::Uuid;
+
+/// An event serialized ready to be saved to a backing store
+///
+/// Records let store adapters save events of different types together, e.g. every variant of an
+/// [`Events`] enum in one batch.
+#[derive(Debug, Clone)]
+pub struct EventRecord {
+    /// Event UUID
+    pub id: Uuid,
+
+    /// Serialized event data, including `event_namespace` and `event_type`
+    pub data: JsonValue,
+
+    /// Serialized event context
+    pub context: JsonValue,
+
+    /// Event creation time, copied from the context
+    pub time: DateTime<Utc>,
+
+    /// ID of the stream this event belongs to
+    pub stream_id: Option<String>,
+
+    /// Position of this event in its stream
+    pub sequence: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct RecordFields {
+    id: Uuid,
+    data: JsonValue,
+    context: JsonValue,
+    stream_id: Option<String>,
+    sequence: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct ContextTime {
+    time: DateTime<Utc>,
+}
+
+impl EventRecord {
+    /// Create a record from a single event
+    pub fn from_event<ED, S>(event: &Event<ED, S>) -> Result<Self, Error>
+    where
+        ED: EventData,
+        S: Serialize,
+    {
+        Self::from_serializable(event)
+    }
+
+    /// Create a record from a variant of an events enum
+    pub fn from_events<E>(event: &E) -> Result<Self, Error>
+    where
+        E: Events,
+    {
+        Self::from_serializable(event)
+    }
+
+    fn from_serializable<S>(event: &S) -> Result<Self, Error>
+    where
+        S: Serialize,
+    {
+        let value = to_value(event)?;
+
+        let fields: RecordFields =
+            from_value(value.clone()).map_err(|e| Error::decode(&value, e))?;
+
+        let time = from_value::<ContextTime>(fields.context.clone())
+            .map_err(|e| Error::decode(&value, e))?
+            .time;
+
+        Ok(Self {
+            id: fields.id,
+            data: fields.data,
+            context: fields.context,
+            time,
+            stream_id: fields.stream_id,
+            sequence: fields.sequence,
+        })
+    }
+
+    /// The event's namespace, taken from its data
+    pub fn event_namespace(&self) -> Option<&str> {
+        self.data["event_namespace"].as_str()
+    }
+
+    /// The event's type, taken from its data
+    pub fn event_type(&self) -> Option<&str> {
+        self.data["event_type"].as_str()
+    }
+
+    /// The event's `namespace.type`, used as the routing key when emitting it
+    pub fn event_namespace_and_type(&self) -> Option<String> {
+        match (self.event_namespace(), self.event_type()) {
+            (Some(event_namespace), Some(event_type)) => {
+                Some(format!("{}.{}", event_namespace, event_type))
+            }
+            _ => None,
+        }
+    }
+
+    /// Serialize the record in the same shape as an [`Event`]
+    ///
+    /// Records are created before the store assigns the event a position, so there's no
+    /// `position` field, just as there isn't one when serializing an event that hasn't been read
+    /// back from the store.
+    pub fn to_json(&self) -> JsonValue {
+        json!({
+            "id": self.id,
+            "data": self.data,
+            "context": self.context,
+            "stream_id": self.stream_id,
+            "sequence": self.sequence,
+        })
+    }
+}
@@ -0,0 +1,536 @@
+use super::{
+    check_version, expecting, stream_conflict, stream_events, stream_rows, verify_duplicate,
+    DecodePolicy, EventRecord, EventStream, SaveStatus, StoreAdapter, PAGE_SIZE,
+};
+use crate::adapters::AdapterFuture;
+use crate::error::Error;
+use crate::event::Event;
+use crate::event_query::{field_path, subject_field_path, EventQuery};
+use crate::store_query::StoreQuery;
+use chrono::prelude::*;
+use event_store_derive_internals::EventData;
+use event_store_derive_internals::Events;
+use log::{debug, trace};
+use rusqlite::types::ToSql;
+use rusqlite::{
+    ffi, Connection, Error as SqliteError, OptionalExtension, Row, TransactionBehavior,
+};
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex};
+
+const INIT_QUERIES: &str = r#"
+-- Create events table if it doesn't already exist
+create table if not exists events(
+    id text primary key,
+    data text not null,
+    context text default '{}',
+    stream_id text,
+    sequence integer,
+    event_namespace text,
+    event_type text,
+    position integer not null
+);
+
+-- Global position of each event, used to order reads
+create unique index if not exists events_position on events (position asc);
+
+-- Prevent two events from taking the same position in a stream
+create unique index if not exists events_stream_sequence on events (stream_id, sequence);
+
+-- Add index on time to speed up filtering
+create index if not exists counter_time on events (julianday(json_extract(context, '$.time')) asc);
+
+-- Create index to speed up queries by type
+create index if not exists event_type_legacy on events (json_extract(data, '$.type'));
+create index if not exists events_namespace_type on events (event_namespace, event_type);
+"#;
+
+/// The columns SQLite names in a constraint error when an event is saved at a taken stream sequence
+const STREAM_SEQUENCE_COLUMNS: &str = "events.stream_id, events.sequence";
+
+/// Representation of a SQLite query and args
+///
+/// Queries can use SQLite's JSON1 functions, e.g. `json_extract(data, '$.num')`, to filter on
+/// event data and context. Filter on the indexed `event_namespace` and `event_type` columns
+/// instead of the same fields in `data`. Queries must select the `position` column, e.g.
+/// `select * from events where ...`, as results are ordered by it.
+pub struct SqliteQuery {
+    /// Query string with placeholders
+    pub query: String,
+
+    /// Arguments to use for the query
+    pub args: Vec<Box<ToSql + Send + Sync>>,
+}
+
+impl SqliteQuery {
+    /// Create a new query from a query string and arguments
+    pub fn new(query: &str, args: Vec<Box<ToSql + Send + Sync>>) -> Self {
+        Self {
+            query: query.into(),
+            args,
+        }
+    }
+}
+
+impl StoreQuery for SqliteQuery {
+    fn unique_id(&self) -> String {
+        let args = self
+            .args
+            .iter()
+            .map(|arg| format!("{:?}", arg.to_sql().ok()))
+            .collect::<Vec<String>>();
+
+        let hash = Sha256::digest(format!("{:?}:[{}]", args, self.query).as_bytes());
+        hash.iter().fold(String::new(), |mut acc, hex| {
+            acc.push_str(&format!("{:X}", hex));
+            acc
+        })
+    }
+}
+
+impl From<EventQuery> for SqliteQuery {
+    fn from(query: EventQuery) -> Self {
+        let mut conditions = Vec::new();
+        let mut args: Vec<Box<ToSql + Send + Sync>> = Vec::new();
+
+        if !query.event_types.is_empty() {
+            let types = query
+                .event_types
+                .into_iter()
+                .map(|(event_namespace, event_type)| {
+                    format!(
+                        "(event_namespace = {} and event_type = {})",
+                        push_arg(&mut args, event_namespace),
+                        push_arg(&mut args, event_type)
+                    )
+                })
+                .collect::<Vec<String>>();
+
+            conditions.push(format!("({})", types.join(" or ")));
+        }
+
+        if !query.namespaces.is_empty() {
+            let namespaces = query
+                .namespaces
+                .into_iter()
+                .map(|event_namespace| push_arg(&mut args, event_namespace))
+                .collect::<Vec<String>>();
+
+            conditions.push(format!("event_namespace in ({})", namespaces.join(", ")));
+        }
+
+        if let Some(stream_id) = query.stream_id {
+            conditions.push(format!("stream_id = {}", push_arg(&mut args, stream_id)));
+        }
+
+        let fields = query
+            .fields
+            .into_iter()
+            .map(|(path, value)| ("data", field_path(&path), value))
+            .chain(
+                query
+                    .subject_fields
+                    .into_iter()
+                    .map(|(path, value)| ("context", subject_field_path(&path), value)),
+            );
+
+        for (column, names, value) in fields {
+            let json_path = names.iter().fold(String::from("$"), |acc, name| {
+                format!("{}.\"{}\"", acc, name)
+            });
+
+            // Extract the value from its JSON text so it compares the same way as the stored field
+            conditions.push(format!(
+                "json_extract({}, {}) = json_extract({}, '$')",
+                column,
+                push_arg(&mut args, json_path),
+                push_arg(&mut args, value.to_string())
+            ));
+        }
+
+        if let Some(since) = query.since {
+            conditions.push(format!(
+                "julianday(json_extract(context, '$.time')) >= julianday({})",
+                push_arg(&mut args, since.to_rfc3339())
+            ));
+        }
+
+        if let Some(before) = query.before {
+            conditions.push(format!(
+                "julianday(json_extract(context, '$.time')) < julianday({})",
+                push_arg(&mut args, before.to_rfc3339())
+            ));
+        }
+
+        if let Some(position) = query.after_position {
+            conditions.push(format!("position > {}", push_arg(&mut args, position)));
+        }
+
+        if let Some(position) = query.up_to_position {
+            conditions.push(format!("position <= {}", push_arg(&mut args, position)));
+        }
+
+        let query_string = if conditions.is_empty() {
+            String::from("select * from events")
+        } else {
+            format!("select * from events where {}", conditions.join(" and "))
+        };
+
+        Self::new(&query_string, args)
+    }
+}
+
+/// Add an argument to a query being built, returning its placeholder
+fn push_arg<T>(args: &mut Vec<Box<ToSql + Send + Sync>>, arg: T) -> String
+where
+    T: ToSql + Send + Sync + 'static,
+{
+    args.push(Box::new(arg));
+
+    format!("?{}", args.len())
+}
+
+fn generate_query(initial_query: &SqliteQuery, since: Option<DateTime<Utc>>, after: i64) -> String {
+    let since_filter = since
+        .map(|timestamp| {
+            format!(
+                " and julianday(json_extract(events.context, '$.time')) >= julianday('{}')",
+                timestamp.to_rfc3339()
+            )
+        })
+        .unwrap_or_default();
+
+    format!(
+        "select * from ({}) as events where events.position > {}{} order by events.position asc limit {}",
+        initial_query.query, after, since_filter, PAGE_SIZE,
+    )
+}
+
+fn row_to_json(row: &Row) -> Result<JsonValue, SqliteError> {
+    let id: String = row.get("id")?;
+    let data_json: JsonValue = row.get("data")?;
+    let context_json: JsonValue = row.get("context")?;
+
+    // Queries may not select the stream columns
+    let stream_id: Option<String> = row.get("stream_id").unwrap_or(None);
+    let sequence: Option<i64> = row.get("sequence").unwrap_or(None);
+    let position: Option<i64> = row.get("position").ok();
+
+    Ok(json!({
+        "id": id,
+        "data": data_json,
+        "context": context_json,
+        "stream_id": stream_id,
+        "sequence": sequence,
+        "position": position,
+    }))
+}
+
+pub(crate) fn poisoned<T>(_: T) -> Error {
+    Error::Connection("SQLite connection lock poisoned".into())
+}
+
+/// SQLite-backed store adapter
+///
+/// Suitable for embedded use where a single file holds all events. The connection can be shared
+/// with a [`crate::adapters::SqliteCacheAdapter`] so that events and the aggregate cache live in
+/// the same file.
+#[derive(Clone)]
+pub struct SqliteStoreAdapter {
+    conn: Arc<Mutex<Connection>>,
+    decode_policy: DecodePolicy,
+    verify_duplicates: bool,
+}
+
+impl SqliteStoreAdapter {
+    /// Create a new SQLite store
+    ///
+    /// This will attempt to create the events table and indexes if they do not already exist
+    pub async fn new(conn: Arc<Mutex<Connection>>) -> Result<Self, Error> {
+        conn.lock().map_err(poisoned)?.execute_batch(INIT_QUERIES)?;
+
+        Ok(Self {
+            conn,
+            decode_policy: DecodePolicy::default(),
+            verify_duplicates: false,
+        })
+    }
+
+    /// Set how events that can't be decoded are handled when reading. Defaults to
+    /// [`DecodePolicy::Fail`].
+    pub fn with_decode_policy(self, decode_policy: DecodePolicy) -> Self {
+        Self {
+            decode_policy,
+            ..self
+        }
+    }
+
+    /// Check that events saved with an ID that already exists have the same data and context as
+    /// the stored event
+    ///
+    /// Saving such an event resolves to [`Error::Duplicate`] if they differ, instead of
+    /// `SaveStatus::Duplicate`.
+    pub fn with_duplicate_verification(self) -> Self {
+        Self {
+            verify_duplicates: true,
+            ..self
+        }
+    }
+
+    fn read_page(
+        &self,
+        query: &SqliteQuery,
+        since: Option<DateTime<Utc>>,
+        after: i64,
+    ) -> Result<Vec<JsonValue>, Error> {
+        let query_string = generate_query(query, since, after);
+
+        debug!("Read query {}", query_string);
+
+        let conn = self.conn.lock().map_err(poisoned)?;
+
+        let mut stmt = conn.prepare(&query_string)?;
+
+        let params = query.args.iter().map(|arg| &**arg as &ToSql);
+
+        let rows = stmt
+            .query_map(params, row_to_json)?
+            .collect::<Result<Vec<JsonValue>, _>>()?;
+
+        Ok(rows)
+    }
+
+    /// Insert a record, checking that its stream is at `expected_version` first if given
+    fn insert(
+        &self,
+        conn: &Connection,
+        record: &EventRecord,
+        expected_version: Option<i64>,
+    ) -> Result<SaveStatus, Error> {
+        if let (Some(stream_id), Some(expected_version)) = (&record.stream_id, expected_version) {
+            let exists: bool = conn.query_row(
+                "select exists(select 1 from events where id = ?1)",
+                &[&record.id.to_string()],
+                |row| row.get(0),
+            )?;
+
+            // Duplicates resolve the same way as without an expected version
+            if !exists {
+                check_version(stream_id, expected_version, version(conn, stream_id)?)?;
+            }
+        }
+
+        let status = insert_event(conn, record)?;
+
+        if status == SaveStatus::Duplicate && self.verify_duplicates {
+            let stored: Option<(JsonValue, JsonValue)> = conn
+                .query_row(
+                    "select data, context from events where id = ?1 limit 1",
+                    &[&record.id.to_string()],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+
+            if let Some((data, context)) = stored {
+                return verify_duplicate(record, &data, &context);
+            }
+        }
+
+        Ok(status)
+    }
+}
+
+fn version(conn: &Connection, stream_id: &str) -> Result<i64, Error> {
+    let version = conn.query_row(
+        "select coalesce(max(sequence), 0) from events where stream_id = ?1",
+        &[&stream_id as &ToSql],
+        |row| row.get(0),
+    )?;
+
+    Ok(version)
+}
+
+fn insert_event(conn: &Connection, record: &EventRecord) -> Result<SaveStatus, Error> {
+    debug!(
+        "Insert event {} ({})",
+        record
+            .event_namespace_and_type()
+            .unwrap_or_else(|| String::from("(unknown type)")),
+        record.id
+    );
+
+    let result = conn.execute(
+        r#"insert into events (id, data, context, stream_id, sequence, event_namespace, event_type, position)
+            values (?1, ?2, ?3, ?4, ?5, ?6, ?7, (select coalesce(max(position), 0) + 1 from events))"#,
+        &[
+            &record.id.to_string() as &ToSql,
+            &record.data,
+            &record.context,
+            &record.stream_id,
+            &record.sequence,
+            &record.event_namespace(),
+            &record.event_type(),
+        ],
+    );
+
+    match result {
+        Ok(_) => Ok(SaveStatus::Ok),
+        Err(SqliteError::SqliteFailure(ref err, _))
+            if err.extended_code == ffi::SQLITE_CONSTRAINT_PRIMARYKEY =>
+        {
+            Ok(SaveStatus::Duplicate)
+        }
+        Err(SqliteError::SqliteFailure(ref err, Some(ref message)))
+            if err.extended_code == ffi::SQLITE_CONSTRAINT_UNIQUE
+                && message.ends_with(STREAM_SEQUENCE_COLUMNS) =>
+        {
+            match (&record.stream_id, record.sequence) {
+                (Some(stream_id), Some(sequence)) => Err(stream_conflict(stream_id, sequence)),
+                _ => Err(Error::Database(format!("Could not save event: {}", err))),
+            }
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+impl StoreAdapter for SqliteStoreAdapter {
+    type Query = SqliteQuery;
+
+    fn save<'a, ED, S>(&'a self, event: &'a Event<ED, S>) -> AdapterFuture<'a, SaveStatus>
+    where
+        ED: EventData,
+        S: Serialize,
+    {
+        let record = EventRecord::from_event(event);
+
+        Box::pin(async move { self.insert(&*self.conn.lock().map_err(poisoned)?, &record?, None) })
+    }
+
+    fn save_expecting<'a, ED, S>(
+        &'a self,
+        event: &'a Event<ED, S>,
+        expected_version: i64,
+    ) -> AdapterFuture<'a, SaveStatus>
+    where
+        ED: EventData,
+        S: Serialize,
+    {
+        debug!("Save event expecting stream version {}", expected_version);
+
+        let record = expecting(event, expected_version);
+
+        Box::pin(async move {
+            let mut conn = self.conn.lock().map_err(poisoned)?;
+
+            // Take the write lock before reading the stream version so that other connections to
+            // the same database can't save to the stream in between
+            let trans = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+            let status = self.insert(&trans, &record?, Some(expected_version))?;
+
+            trans.commit()?;
+
+            Ok(status)
+        })
+    }
+
+    fn save_all<'a>(&'a self, records: &'a [EventRecord]) -> AdapterFuture<'a, Vec<SaveStatus>> {
+        Box::pin(async move {
+            debug!("Save batch of {} events", records.len());
+
+            let mut conn = self.conn.lock().map_err(poisoned)?;
+
+            let trans = conn.transaction()?;
+
+            let statuses = records
+                .iter()
+                .map(|record| self.insert(&trans, record, None))
+                .collect::<Result<Vec<SaveStatus>, Error>>()?;
+
+            trans.commit()?;
+
+            Ok(statuses)
+        })
+    }
+
+    fn decode_policy(&self) -> &DecodePolicy {
+        &self.decode_policy
+    }
+
+    fn stream_version<'a>(&'a self, stream_id: &'a str) -> AdapterFuture<'a, i64> {
+        Box::pin(async move {
+            let conn = self.conn.lock().map_err(poisoned)?;
+
+            version(&conn, stream_id)
+        })
+    }
+
+    fn stream<'a, E>(
+        &'a self,
+        query: &'a SqliteQuery,
+        since: Option<DateTime<Utc>>,
+    ) -> EventStream<'a, E>
+    where
+        E: Events + Send + 'a,
+    {
+        stream_events(0, self.decode_policy.clone(), move |after| {
+            self.read_page(query, since, after)
+        })
+    }
+
+    fn stream_after<'a, E>(&'a self, query: &'a SqliteQuery, position: i64) -> EventStream<'a, E>
+    where
+        E: Events + Send + 'a,
+    {
+        stream_events(position, self.decode_policy.clone(), move |after| {
+            self.read_page(query, None, after)
+        })
+    }
+
+    fn stream_events_since<'a>(
+        &'a self,
+        event_namespace: &'a str,
+        event_type: &'a str,
+        since: DateTime<Utc>,
+    ) -> EventStream<'a, JsonValue> {
+        trace!(
+            "Read events of type {}.{} since {}",
+            event_namespace,
+            event_type,
+            since.to_rfc3339()
+        );
+
+        let since = since.to_rfc3339();
+
+        stream_rows(0, move |after| {
+            let conn = self.conn.lock().map_err(poisoned)?;
+
+            let mut stmt = conn.prepare(
+                r#"select * from events
+                    where event_namespace = ?1
+                    and event_type = ?2
+                    and julianday(json_extract(context, '$.time')) >= julianday(?3)
+                    and position > ?4
+                    order by position asc
+                    limit ?5"#,
+            )?;
+
+            let rows = stmt
+                .query_map(
+                    &[
+                        &event_namespace as &ToSql,
+                        &event_type,
+                        &since,
+                        &after,
+                        &PAGE_SIZE,
+                    ],
+                    row_to_json,
+                )?
+                .collect::<Result<Vec<JsonValue>, _>>()?;
+
+            Ok(rows)
+        })
+    }
+}
@@ -0,0 +1,69 @@
+use super::{decode_events, DecodePolicy};
+use crate::error::Error;
+use event_store_derive_internals::Events;
+use futures::{future, stream, Stream};
+use serde_json::Value as JsonValue;
+
+/// Stream of items read from a backing store, in position order
+///
+/// Store adapters read events a page at a time as the stream is polled, so only one page is held
+/// in memory at once.
+pub type EventStream<'a, T> = Box<dyn Stream<Item = T, Error = Error> + Send + 'a>;
+
+/// Number of rows fetched from a backing store at a time when streaming events
+pub(crate) const PAGE_SIZE: i64 = 1000;
+
+/// Fetch pages of rows using `fetch_page`, which is given the position of the last row fetched
+/// and must return up to [`PAGE_SIZE`] rows with a greater position, in position order
+///
+/// Rows must have a `position` field. Paging starts after `after`, and stops after the first page
+/// holding fewer than [`PAGE_SIZE`] rows.
+fn pages<'a, F>(
+    after: i64,
+    mut fetch_page: F,
+) -> impl Stream<Item = Vec<JsonValue>, Error = Error> + Send + 'a
+where
+    F: FnMut(i64) -> Result<Vec<JsonValue>, Error> + Send + 'a,
+{
+    stream::unfold(Some(after), move |after| {
+        let after = after?;
+
+        let page = fetch_page(after).map(|rows| {
+            let next = if (rows.len() as i64) < PAGE_SIZE {
+                None
+            } else {
+                rows.last().and_then(|row| row["position"].as_i64())
+            };
+
+            (rows, next)
+        });
+
+        Some(future::result(page))
+    })
+}
+
+/// Stream raw rows fetched a page at a time
+pub(crate) fn stream_rows<'a, F>(after: i64, fetch_page: F) -> EventStream<'a, JsonValue>
+where
+    F: FnMut(i64) -> Result<Vec<JsonValue>, Error> + Send + 'a,
+{
+    Box::new(pages(after, fetch_page).map(stream::iter_ok).flatten())
+}
+
+/// Stream events fetched a page at a time, decoding each page according to `policy`
+pub(crate) fn stream_events<'a, E, F>(
+    after: i64,
+    policy: DecodePolicy,
+    fetch_page: F,
+) -> EventStream<'a, E>
+where
+    E: Events + Send + 'a,
+    F: FnMut(i64) -> Result<Vec<JsonValue>, Error> + Send + 'a,
+{
+    Box::new(
+        pages(after, fetch_page)
+            .and_then(move |rows| decode_events(rows, &policy))
+            .map(stream::iter_ok)
+            .flatten(),
+    )
+}
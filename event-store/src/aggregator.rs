@@ -3,17 +3,96 @@
 use crate::store_query::StoreQuery;
 use event_store_derive_internals::Events;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, to_value, Value as JsonValue};
+use sha2::{Digest, Sha256};
 use std::fmt::Debug;
 
+/// How an aggregator's state is snapshotted when aggregating over a single stream
+///
+/// Snapshots are saved in the cache keyed by `aggregate_type`, the stream ID, the query's unique
+/// ID and `version`, along with the position of the last event applied. Aggregation over a stream
+/// starts from its latest snapshot with the current `version`, so bump `version` whenever the
+/// aggregate's fields or [`Aggregator::apply_event`] change in a way that makes old snapshots
+/// invalid. Like cached results, snapshots are also discarded if the aggregate's cache
+/// fingerprint has changed since they were saved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnapshotPolicy {
+    /// Name identifying the aggregate type, unique among aggregators over the same streams
+    pub aggregate_type: &'static str,
+
+    /// Number of events to apply between saving snapshots
+    ///
+    /// Must be greater than 0; aggregating with a frequency of 0 fails with
+    /// [`crate::Error::Configuration`].
+    pub frequency: u64,
+
+    /// Schema version of the aggregate type
+    pub version: u32,
+}
+
 // TODO: Port docs from `_event-store/src/aggregator`
 /// Aggregator trait
 pub trait Aggregator<E: Events, A: Clone, Q: StoreQuery>:
     Clone + Debug + Default + PartialEq + Serialize + for<'de> Deserialize<'de>
 {
+    /// Snapshot settings, or `None` to never snapshot this aggregate
+    ///
+    /// Snapshots are only used for queries over a single stream, i.e. where
+    /// [`StoreQuery::stream_id`] returns the stream's ID.
+    const SNAPSHOTS: Option<SnapshotPolicy> = None;
+
+    /// Version of the aggregate's cached results
+    ///
+    /// Cached results are discarded and recomputed if the JSON shape of the aggregate's default
+    /// value changes, e.g. when a field is added. The shape only covers what the default value
+    /// holds, so changes inside types that are empty by default, like the item type of a `Vec` or
+    /// the inner type of an `Option` that defaults to `None`, aren't detected.
+    ///
+    /// Bump this when such an inner type changes, or when [`Aggregator::apply_event`] changes
+    /// without the shape changing, to discard results folded by the old code.
+    const CACHE_VERSION: u32 = 1;
+
     /// Apply an event `E` to `acc`, returning a copy of `Self` with updated fields. Can also just
     /// return `acc` if nothing has changed.
     fn apply_event(acc: Self, event: &E) -> Self;
 
     /// Produce a query object from some query arguments
+    ///
+    /// Return an [`crate::EventQuery`] to aggregate over any store adapter, or a store's own query
+    /// type to use features only that backend supports.
     fn query(query_args: A) -> Q;
 }
+
+/// Fingerprint of an aggregate type's cached results, from the JSON shape of its default value
+/// and its [`Aggregator::CACHE_VERSION`]
+pub(crate) fn cache_fingerprint<T>(default: &T, cache_version: u32) -> String
+where
+    T: Serialize,
+{
+    let shape = to_value(default)
+        .map(|value| json_shape(&value))
+        .unwrap_or(JsonValue::Null);
+
+    let hash = Sha256::digest(format!("{}:{}", cache_version, shape).as_bytes());
+    hash.iter().fold(String::new(), |mut acc, hex| {
+        acc.push_str(&format!("{:X}", hex));
+        acc
+    })
+}
+
+/// The field names and value types of a JSON value, without the values themselves
+fn json_shape(value: &JsonValue) -> JsonValue {
+    match value {
+        JsonValue::Null => json!("null"),
+        JsonValue::Bool(_) => json!("bool"),
+        JsonValue::Number(_) => json!("number"),
+        JsonValue::String(_) => json!("string"),
+        JsonValue::Array(items) => JsonValue::Array(items.iter().map(json_shape).collect()),
+        JsonValue::Object(fields) => JsonValue::Object(
+            fields
+                .iter()
+                .map(|(name, value)| (name.clone(), json_shape(value)))
+                .collect(),
+        ),
+    }
+}
@@ -0,0 +1,189 @@
+//! Error types
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use uuid::Uuid;
+
+/// An error returned from the event store or one of its adapters
+#[derive(Debug)]
+pub enum Error {
+    /// A backing service could not be reached, or the connection to it failed
+    Connection(String),
+
+    /// A backing service was reached but rejected an operation, for example because a query was
+    /// invalid or violated a constraint
+    Database(String),
+
+    /// A value could not be serialized or deserialized to or from JSON
+    Serialization(String),
+
+    /// An event could not be decoded into the requested type
+    Decode {
+        /// The event's ID, if it could be read
+        id: Option<Uuid>,
+
+        /// The event's `namespace.type`, if it could be read
+        event_namespace_and_type: Option<String>,
+
+        /// The reason decoding failed
+        reason: String,
+    },
+
+    /// An event with this ID already exists in the store, but with different data or context
+    Duplicate(Uuid),
+
+    /// An event could not be saved because another writer saved a conflicting event first
+    Concurrency(String),
+
+    /// An event handler failed to handle an event
+    Handler(HandlerError),
+
+    /// An aggregator or adapter has an invalid setting
+    Configuration(String),
+}
+
+impl Error {
+    /// Create a decode error for an event given as JSON, reading its ID and `namespace.type` if
+    /// they are present
+    pub fn decode<E>(event: &serde_json::Value, reason: E) -> Self
+    where
+        E: ToString,
+    {
+        let id = event["id"].as_str().and_then(|id| Uuid::parse_str(id).ok());
+
+        let event_namespace_and_type = match (
+            event["data"]["event_namespace"].as_str(),
+            event["data"]["event_type"].as_str(),
+        ) {
+            (Some(event_namespace), Some(event_type)) => {
+                Some(format!("{}.{}", event_namespace, event_type))
+            }
+            _ => None,
+        };
+
+        Error::Decode {
+            id,
+            event_namespace_and_type,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Connection(message) => write!(f, "Connection error: {}", message),
+            Error::Database(message) => write!(f, "Database error: {}", message),
+            Error::Serialization(message) => write!(f, "Serialization error: {}", message),
+            Error::Decode {
+                id,
+                event_namespace_and_type,
+                reason,
+            } => write!(
+                f,
+                "Failed to decode event {} (ID {}): {}",
+                event_namespace_and_type
+                    .as_ref()
+                    .map(|s| s.as_str())
+                    .unwrap_or("(unknown type)"),
+                id.map(|id| id.to_string())
+                    .unwrap_or_else(|| String::from("unknown")),
+                reason
+            ),
+            Error::Duplicate(id) => write!(f, "Event {} already exists with different content", id),
+            Error::Concurrency(message) => write!(f, "Concurrency conflict: {}", message),
+            Error::Handler(err) => write!(f, "Handler error: {}", err),
+            Error::Configuration(message) => write!(f, "Configuration error: {}", message),
+        }
+    }
+}
+
+impl StdError for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Connection(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Serialization(err.to_string())
+    }
+}
+
+impl From<postgres::Error> for Error {
+    fn from(err: postgres::Error) -> Self {
+        // SQLSTATE class 08 is "connection exception"
+        let rejected = err.as_conversion().is_some()
+            || err
+                .code()
+                .map_or(false, |code| !code.code().starts_with("08"));
+
+        if rejected {
+            Error::Database(err.to_string())
+        } else {
+            Error::Connection(err.to_string())
+        }
+    }
+}
+
+impl From<r2d2::Error> for Error {
+    fn from(err: r2d2::Error) -> Self {
+        Error::Connection(err.to_string())
+    }
+}
+
+impl From<redis::RedisError> for Error {
+    fn from(err: redis::RedisError) -> Self {
+        Error::Connection(err.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        use rusqlite::ErrorCode;
+
+        match err {
+            rusqlite::Error::SqliteFailure(ref failure, _)
+                if failure.code == ErrorCode::SystemIOFailure
+                    || failure.code == ErrorCode::CannotOpen =>
+            {
+                Error::Connection(err.to_string())
+            }
+            rusqlite::Error::InvalidPath(_) => Error::Connection(err.to_string()),
+            _ => Error::Database(err.to_string()),
+        }
+    }
+}
+
+impl From<HandlerError> for Error {
+    fn from(err: HandlerError) -> Self {
+        Error::Handler(err)
+    }
+}
+
+/// An error returned from [`crate::EventHandler::handle_event`]
+///
+/// The variant decides what happens to the event's message: retried events are left to be
+/// redelivered by the emitter, failed events are dropped.
+#[derive(Debug)]
+pub enum HandlerError {
+    /// The event could not be handled this time, but might be if it is delivered again
+    Retry(String),
+
+    /// The event can never be handled and should not be delivered again
+    Failed(String),
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandlerError::Retry(message) => write!(f, "{} (will retry)", message),
+            HandlerError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl StdError for HandlerError {}
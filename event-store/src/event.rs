@@ -1,36 +1,50 @@
-use crate::event_context::EventContext;
+use crate::event_context::{EventCause, EventContext};
 use chrono::prelude::*;
 use event_store_derive_internals::EventData;
 use serde_derive::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
 use uuid::Uuid;
 
 /// Event with `EventData`, `EventContext` and a `Uuid` ID
 ///
-/// This is what gets stored in the store and emitted from the emitter
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Event<D> {
+/// This is what gets stored in the store and emitted from the emitter. `S` is the type of the
+/// context's subject.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Event<D, S = JsonValue> {
     /// Event data payload
     pub data: D,
 
     /// Event context
-    pub context: EventContext,
+    pub context: EventContext<S>,
 
     /// Event UUID
     pub id: Uuid,
+
+    /// ID of the stream this event belongs to, usually the ID of the entity it changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_id: Option<String>,
+
+    /// Position of this event in its stream, starting at 1
+    ///
+    /// Set by the store when the event is saved with [`crate::Store::save_expecting`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<i64>,
+
+    /// Position of this event in the store, across all streams
+    ///
+    /// Positions only ever increase, so they give a total order of events. Set by the store when
+    /// the event is read back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<i64>,
 }
 
 impl<D> Event<D>
 where
     D: EventData,
 {
-    /// Create a new event
-    pub fn new(data: D, id: Uuid, context: EventContext) -> Self {
-        Self { data, context, id }
-    }
-
     /// Create a new event from some data. `context.time` is set to now, `id` to a new V4 ID
     ///
-    /// The rest of the context is left empty
+    /// The rest of the context is left empty. Use [`Event::with_subject`] to add a subject.
     pub fn from_data(data: D) -> Self {
         Self {
             data,
@@ -39,7 +53,29 @@ where
                 action: None,
                 subject: None,
                 time: Utc::now(),
+                correlation_id: None,
+                causation_id: None,
             },
+            stream_id: None,
+            sequence: None,
+            position: None,
+        }
+    }
+}
+
+impl<D, S> Event<D, S>
+where
+    D: EventData,
+{
+    /// Create a new event
+    pub fn new(data: D, id: Uuid, context: EventContext<S>) -> Self {
+        Self {
+            data,
+            context,
+            id,
+            stream_id: None,
+            sequence: None,
+            position: None,
         }
     }
 
@@ -47,4 +83,42 @@ where
     pub fn with_id(self, id: Uuid) -> Self {
         Self { id, ..self }
     }
+
+    /// Create a copied event with the given context subject, which may be of a different type
+    pub fn with_subject<T>(self, subject: T) -> Event<D, T> {
+        Event {
+            data: self.data,
+            context: self.context.with_subject(subject),
+            id: self.id,
+            stream_id: self.stream_id,
+            sequence: self.sequence,
+            position: self.position,
+        }
+    }
+
+    /// Create a copied event caused by `cause`
+    ///
+    /// The copy's causation ID is set to the ID of `cause`, and its correlation ID to that of
+    /// `cause`, or the ID of `cause` if it has no correlation ID. Events saved through the store
+    /// given to an [`crate::EventHandler`] are marked as caused by the handled event automatically.
+    pub fn caused_by<C, CS>(self, cause: &Event<C, CS>) -> Self {
+        let cause = EventCause::new(cause.id, &cause.context);
+
+        Self {
+            context: EventContext {
+                correlation_id: Some(cause.correlation_id),
+                causation_id: Some(cause.causation_id),
+                ..self.context
+            },
+            ..self
+        }
+    }
+
+    /// Create a copied event belonging to the given stream
+    pub fn with_stream_id(self, stream_id: &str) -> Self {
+        Self {
+            stream_id: Some(stream_id.into()),
+            ..self
+        }
+    }
 }
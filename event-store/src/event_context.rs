@@ -1,18 +1,125 @@
 use chrono::prelude::*;
+use serde::ser::SerializeMap;
+use serde::{de, Deserializer, Serializer};
 use serde_derive::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
+use uuid::Uuid;
 
 /// Event context
 ///
-/// Contains metadata for event and, most importantly, the creation time
+/// Contains metadata for event and, most importantly, the creation time. The subject is untyped
+/// JSON by default, but can be any serializable type `S`, e.g. a struct holding a user and
+/// organisation ID.
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct EventContext {
+#[serde(bound(
+    serialize = "S: serde::Serialize",
+    deserialize = "S: serde::Deserialize<'de>"
+))]
+pub struct EventContext<S = JsonValue> {
     /// TODO: What is this?
     pub action: Option<String>,
 
     /// Optional event "subject" or metadata
-    pub subject: Option<JsonValue>,
+    ///
+    /// Legacy events store a missing subject as an empty object `{}`, so `None` is serialized as
+    /// `{}` and a missing, `null` or empty object subject is deserialized as `None`.
+    #[serde(
+        default,
+        serialize_with = "serialize_subject",
+        deserialize_with = "deserialize_subject"
+    )]
+    pub subject: Option<S>,
 
     /// Event creation time
     pub time: DateTime<Utc>,
+
+    /// ID shared by every event in the same workflow, usually the ID of the event that started it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<Uuid>,
+
+    /// ID of the event that caused this one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub causation_id: Option<Uuid>,
+}
+
+impl<S> EventContext<S> {
+    /// Create a copied context with the given subject, which may be of a different type
+    pub fn with_subject<T>(self, subject: T) -> EventContext<T> {
+        EventContext {
+            action: self.action,
+            subject: Some(subject),
+            time: self.time,
+            correlation_id: self.correlation_id,
+            causation_id: self.causation_id,
+        }
+    }
+}
+
+/// Serialize a subject, writing `None` as the empty object `{}`
+fn serialize_subject<Ser, S>(subject: &Option<S>, serializer: Ser) -> Result<Ser::Ok, Ser::Error>
+where
+    Ser: Serializer,
+    S: serde::Serialize,
+{
+    match subject {
+        Some(subject) => subject.serialize(serializer),
+        None => serializer.serialize_map(Some(0))?.end(),
+    }
+}
+
+/// Deserialize a subject, mapping `null` and the empty object `{}` to `None`
+fn deserialize_subject<'de, D, S>(deserializer: D) -> Result<Option<S>, D::Error>
+where
+    D: Deserializer<'de>,
+    S: serde::Deserialize<'de>,
+{
+    match <JsonValue as serde::Deserialize>::deserialize(deserializer)? {
+        JsonValue::Null => Ok(None),
+        JsonValue::Object(ref fields) if fields.is_empty() => Ok(None),
+        subject => S::deserialize(subject).map(Some).map_err(de::Error::custom),
+    }
+}
+
+/// Correlation and causation IDs given to events saved while handling another event
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct EventCause {
+    pub correlation_id: Uuid,
+    pub causation_id: Uuid,
+}
+
+impl EventCause {
+    /// The cause of events saved in response to the event with the given ID and context
+    ///
+    /// The correlation ID is inherited from the cause, or is the cause's own ID if it has none.
+    pub fn new<S>(id: Uuid, context: &EventContext<S>) -> Self {
+        Self {
+            correlation_id: context.correlation_id.unwrap_or(id),
+            causation_id: id,
+        }
+    }
+
+    /// Fill in the correlation and causation IDs of a context, keeping any already set
+    pub fn apply_to_context<S>(&self, context: &mut EventContext<S>) {
+        context.correlation_id = context.correlation_id.or(Some(self.correlation_id));
+        context.causation_id = context.causation_id.or(Some(self.causation_id));
+    }
+
+    /// Fill in the correlation and causation IDs of a serialized context, keeping any already set
+    pub fn apply(&self, context: &mut JsonValue) {
+        if let Some(fields) = context.as_object_mut() {
+            if fields
+                .get("correlation_id")
+                .map_or(true, JsonValue::is_null)
+            {
+                fields.insert(
+                    "correlation_id".into(),
+                    self.correlation_id.to_string().into(),
+                );
+            }
+
+            if fields.get("causation_id").map_or(true, JsonValue::is_null) {
+                fields.insert("causation_id".into(), self.causation_id.to_string().into());
+            }
+        }
+    }
 }
@@ -1,5 +1,7 @@
 //! Event handler trait
 
+use crate::adapters::{CacheAdapter, EmitterAdapter, StoreAdapter};
+use crate::error::HandlerError;
 use crate::event::Event;
 use crate::store::Store;
 use event_store_derive_internals::EventData;
@@ -8,8 +10,17 @@ use event_store_derive_internals::EventData;
 pub trait EventHandler: Sized + EventData {
     /// The method called when an incoming event is received
     ///
-    /// TODO: Come up with a better error type than `()`
-    fn handle_event(_event: Event<Self>, _saver: &Store) -> Result<(), ()> {
+    /// Return [`HandlerError::Retry`] if the event should be delivered again later, or
+    /// [`HandlerError::Failed`] if it can never be handled.
+    fn handle_event<SA, CA, EA>(
+        _event: Event<Self>,
+        _saver: &Store<SA, CA, EA>,
+    ) -> Result<(), HandlerError>
+    where
+        SA: StoreAdapter,
+        CA: CacheAdapter,
+        EA: EmitterAdapter,
+    {
         Ok(())
     }
 }
@@ -0,0 +1,242 @@
+//! Backend-neutral event query builder
+
+use crate::store_query::StoreQuery;
+use chrono::prelude::*;
+use event_store_derive_internals::{EventData, Events};
+use serde_json::{json, Value as JsonValue};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A query for events that any store adapter can run
+///
+/// Aggregators can return an `EventQuery` from [`crate::Aggregator::query`] instead of a query
+/// written for a particular backend. Store adapters convert it to their own query type, e.g.
+/// [`crate::adapters::PgQuery`] with `PgQuery::from(query)`.
+///
+/// Filters of different kinds must all match for an event to be returned. Event types and
+/// namespaces match if the event has any of the given types or namespaces respectively. An empty
+/// query matches every event.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventQuery {
+    pub(crate) event_types: BTreeSet<(String, String)>,
+    pub(crate) namespaces: BTreeSet<String>,
+    pub(crate) stream_id: Option<String>,
+    pub(crate) fields: BTreeMap<String, JsonValue>,
+    pub(crate) subject_fields: BTreeMap<String, JsonValue>,
+    pub(crate) since: Option<DateTime<Utc>>,
+    pub(crate) before: Option<DateTime<Utc>>,
+    pub(crate) after_position: Option<i64>,
+    pub(crate) up_to_position: Option<i64>,
+}
+
+impl EventQuery {
+    /// Create a query matching every event
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a query matching any of the events in an events enum
+    pub fn for_events<E>() -> Self
+    where
+        E: Events,
+    {
+        Self::new().with_events::<E>()
+    }
+
+    /// Also match any of the events in an events enum
+    pub fn with_events<E>(mut self) -> Self
+    where
+        E: Events,
+    {
+        for (event_namespace, event_type) in E::event_namespaces_and_types() {
+            self.event_types
+                .insert((event_namespace.into(), event_type.into()));
+        }
+
+        self
+    }
+
+    /// Also match events of type `ED`
+    pub fn with_event<ED>(mut self) -> Self
+    where
+        ED: EventData,
+    {
+        self.event_types
+            .insert((ED::event_namespace().into(), ED::event_type().into()));
+
+        self
+    }
+
+    /// Also match events in the given namespace
+    pub fn with_namespace(mut self, event_namespace: &str) -> Self {
+        self.namespaces.insert(event_namespace.into());
+
+        self
+    }
+
+    /// Only match events in the given stream
+    pub fn with_stream_id(self, stream_id: &str) -> Self {
+        Self {
+            stream_id: Some(stream_id.into()),
+            ..self
+        }
+    }
+
+    /// Only match events where the data field at `path` is equal to `value`
+    ///
+    /// `path` is a list of field names separated by dots, e.g. `address.postcode`.
+    pub fn with_field<V>(mut self, path: &str, value: V) -> Self
+    where
+        V: Into<JsonValue>,
+    {
+        self.fields.insert(path.into(), value.into());
+
+        self
+    }
+
+    /// Only match events where the context subject field at `path` is equal to `value`
+    ///
+    /// `path` is a list of field names separated by dots, the same as [`EventQuery::with_field`].
+    pub fn with_subject_field<V>(mut self, path: &str, value: V) -> Self
+    where
+        V: Into<JsonValue>,
+    {
+        self.subject_fields.insert(path.into(), value.into());
+
+        self
+    }
+
+    /// Only match events created at or after `since`
+    pub fn since(self, since: DateTime<Utc>) -> Self {
+        Self {
+            since: Some(since),
+            ..self
+        }
+    }
+
+    /// Only match events created before `before`
+    pub fn before(self, before: DateTime<Utc>) -> Self {
+        Self {
+            before: Some(before),
+            ..self
+        }
+    }
+
+    /// Only match events with a position greater than `position`
+    pub fn after_position(self, position: i64) -> Self {
+        Self {
+            after_position: Some(position),
+            ..self
+        }
+    }
+
+    /// Only match events with a position less than or equal to `position`
+    pub fn up_to_position(self, position: i64) -> Self {
+        Self {
+            up_to_position: Some(position),
+            ..self
+        }
+    }
+
+    /// Whether an event, in the JSON shape read from a store, matches this query
+    pub(crate) fn matches(&self, event: &JsonValue) -> bool {
+        let data = &event["data"];
+        let event_namespace = data["event_namespace"].as_str();
+        let event_type = data["event_type"].as_str();
+
+        let time = event["context"]["time"]
+            .as_str()
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| time.with_timezone(&Utc));
+
+        let position = event["position"].as_i64();
+
+        (self.event_types.is_empty()
+            || self.event_types.iter().any(|(ns, ty)| {
+                Some(ns.as_str()) == event_namespace && Some(ty.as_str()) == event_type
+            }))
+            && (self.namespaces.is_empty()
+                || event_namespace
+                    .map(|ns| self.namespaces.contains(ns))
+                    .unwrap_or(false))
+            && self
+                .stream_id
+                .as_ref()
+                .map(|stream_id| event["stream_id"].as_str() == Some(stream_id))
+                .unwrap_or(true)
+            && self.fields.iter().all(|(path, value)| {
+                field_path(path)
+                    .iter()
+                    .fold(data, |field, name| &field[name.as_str()])
+                    == value
+            })
+            && self.subject_fields.iter().all(|(path, value)| {
+                subject_field_path(path)
+                    .iter()
+                    .fold(&event["context"], |field, name| &field[name.as_str()])
+                    == value
+            })
+            && within(self.since, time, |since, time| time >= since)
+            && within(self.before, time, |before, time| time < before)
+            && within(self.after_position, position, |after, position| {
+                position > after
+            })
+            && within(self.up_to_position, position, |up_to, position| {
+                position <= up_to
+            })
+    }
+}
+
+/// Split a dotted field path into field names
+pub(crate) fn field_path(path: &str) -> Vec<String> {
+    path.split('.').map(String::from).collect()
+}
+
+/// Split a dotted subject field path into field names, starting from the event context
+pub(crate) fn subject_field_path(path: &str) -> Vec<String> {
+    let mut names = vec![String::from("subject")];
+
+    names.extend(field_path(path));
+
+    names
+}
+
+/// Check a value against an optional bound, failing if the bound is set but the value is missing
+fn within<T, F>(bound: Option<T>, value: Option<T>, check: F) -> bool
+where
+    F: Fn(T, T) -> bool,
+{
+    match (bound, value) {
+        (Some(bound), Some(value)) => check(bound, value),
+        (Some(_), None) => false,
+        (None, _) => true,
+    }
+}
+
+impl StoreQuery for EventQuery {
+    /// Hash of the query's filters, independent of the order they were added in and the backend
+    /// the query is run against
+    fn unique_id(&self) -> String {
+        let canonical = json!({
+            "event_types": self.event_types,
+            "namespaces": self.namespaces,
+            "stream_id": self.stream_id,
+            "fields": self.fields,
+            "subject_fields": self.subject_fields,
+            "since": self.since,
+            "before": self.before,
+            "after_position": self.after_position,
+            "up_to_position": self.up_to_position,
+        });
+
+        let hash = Sha256::digest(format!("event_query:{}", canonical).as_bytes());
+        hash.iter().fold(String::new(), |mut acc, hex| {
+            acc.push_str(&format!("{:X}", hex));
+            acc
+        })
+    }
+
+    fn stream_id(&self) -> Option<&str> {
+        self.stream_id.as_ref().map(|stream_id| stream_id.as_str())
+    }
+}
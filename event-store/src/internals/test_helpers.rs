@@ -1,15 +1,19 @@
-use crate::adapters::PgQuery;
+use crate::adapters::{CacheAdapter, EmitterAdapter, StoreAdapter};
 use crate::aggregator::Aggregator;
+use crate::error::{Error, HandlerError};
 use crate::event::Event;
 use crate::event_handler::EventHandler;
+use crate::event_query::EventQuery;
+use crate::internals::backward;
 use crate::store::Store;
 use event_store_derive::*;
 use log::trace;
-use postgres::types::ToSql;
 use r2d2::Pool;
 use r2d2_postgres::{PostgresConnectionManager, TlsMode};
 use serde_derive::*;
+use std::future::Future;
 use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::runtime::Runtime;
 
 /// Set of all events in the domain
 #[derive(Events, Debug)]
@@ -17,7 +21,7 @@ pub enum TestEvents {
     Inc(Event<TestEvent>),
 }
 
-#[derive(EventData, Debug)]
+#[derive(EventData, Debug, Clone)]
 #[event_store(namespace = "some_namespace")]
 pub struct TestEvent {
     pub num: i32,
@@ -36,7 +40,7 @@ impl Default for TestCounterEntity {
     }
 }
 
-impl Aggregator<TestEvents, String, PgQuery> for TestCounterEntity {
+impl Aggregator<TestEvents, String, EventQuery> for TestCounterEntity {
     fn apply_event(acc: Self, event: &TestEvents) -> Self {
         let counter = match event {
             TestEvents::Inc(ref inc) => acc.counter + inc.data.num,
@@ -45,15 +49,21 @@ impl Aggregator<TestEvents, String, PgQuery> for TestCounterEntity {
         Self { counter, ..acc }
     }
 
-    fn query(_query_args: String) -> PgQuery {
-        let params: Vec<Box<ToSql + Send + Sync>> = Vec::new();
-
-        PgQuery::new("select * from events", params)
+    fn query(_query_args: String) -> EventQuery {
+        EventQuery::for_events::<TestEvents>()
     }
 }
 
 impl EventHandler for TestEvent {
-    fn handle_event(event: Event<Self>, _store: &Store) -> Result<(), ()> {
+    fn handle_event<SA, CA, EA>(
+        event: Event<Self>,
+        _store: &Store<SA, CA, EA>,
+    ) -> Result<(), HandlerError>
+    where
+        SA: StoreAdapter,
+        CA: CacheAdapter,
+        EA: EmitterAdapter,
+    {
         trace!("TestEvent handler {:?}", event);
 
         Ok(())
@@ -72,8 +82,18 @@ fn current_time_ms() -> u64 {
     in_ms
 }
 
+/// Create a connection manager for a test database
+pub fn pg_connection_manager(url: &str) -> PostgresConnectionManager {
+    PostgresConnectionManager::new(url, TlsMode::None).unwrap()
+}
+
 /// Create a new database with a random name, returning the connection
 pub fn pg_create_random_db(suffix: Option<&str>) -> Pool<PostgresConnectionManager> {
+    r2d2::Pool::new(pg_connection_manager(&pg_create_random_db_url(suffix))).unwrap()
+}
+
+/// Create a new database with a random name, returning its URL
+pub fn pg_create_random_db_url(suffix: Option<&str>) -> String {
     let db_id = format!(
         "eventstorerust-{}-{}",
         current_time_ms(),
@@ -82,24 +102,22 @@ pub fn pg_create_random_db(suffix: Option<&str>) -> Pool<PostgresConnectionManag
 
     println!("Create test DB {}", db_id);
 
-    let manager =
-        PostgresConnectionManager::new("postgres://postgres@localhost:5430", TlsMode::None)
-            .unwrap();
-
-    let pool = r2d2::Pool::new(manager).unwrap();
+    let pool =
+        r2d2::Pool::new(pg_connection_manager("postgres://postgres@localhost:5430")).unwrap();
 
     let conn = pool.get().unwrap();
 
     conn.batch_execute(&format!("CREATE DATABASE \"{}\"", db_id))
         .unwrap();
 
-    let manager = PostgresConnectionManager::new(
-        format!("postgres://postgres@localhost:5430/{}", db_id),
-        TlsMode::None,
-    )
-    .unwrap();
-
-    let pool = r2d2::Pool::new(manager).unwrap();
+    format!("postgres://postgres@localhost:5430/{}", db_id)
+}
 
-    pool
+/// Run a test's async block to completion on a new Tokio runtime, returning its result
+pub fn run_async<F, T>(fut: F) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>> + Send + 'static,
+    T: Send + 'static,
+{
+    Runtime::new().map_err(Error::from)?.block_on(backward(fut))
 }
@@ -11,9 +11,12 @@
 extern crate serde_derive;
 
 mod aggregator;
+mod error;
 mod event;
 mod event_context;
 mod event_handler;
+mod event_query;
+mod outbox;
 mod store;
 mod store_query;
 mod subscribable_store;
@@ -23,11 +26,13 @@ pub mod adapters;
 pub mod internals;
 pub mod prelude;
 
-pub use crate::aggregator::Aggregator;
+pub use crate::aggregator::{Aggregator, SnapshotPolicy};
+pub use crate::error::{Error, HandlerError};
 pub use crate::event::Event;
 pub use crate::event_context::EventContext;
 pub use crate::event_handler::EventHandler;
+pub use crate::event_query::EventQuery;
 pub use crate::store::Store;
 pub use crate::store_query::StoreQuery;
 pub use crate::subscribable_store::SubscribableStore;
-pub use event_store_derive_internals::{EventData, Events};
+pub use event_store_derive_internals::{EventData, Events, Upcaster};
@@ -0,0 +1,80 @@
+use crate::adapters::{EmitterAdapter, StoreAdapter};
+use crate::error::Error;
+use crate::internals::forward;
+use chrono::prelude::*;
+use log::{debug, error, warn};
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+
+/// Maximum number of outbox items to publish before checking for more
+const RELAY_BATCH_SIZE: i64 = 100;
+
+/// Longest time to wait before retrying a failed item
+const MAX_BACKOFF_SECS: i64 = 300;
+
+/// Time to wait before retrying an item that has failed to publish `attempts` times already
+fn backoff(attempts: i32) -> chrono::Duration {
+    let secs = 2i64
+        .pow(attempts.max(0).min(16) as u32)
+        .min(MAX_BACKOFF_SECS);
+
+    chrono::Duration::seconds(secs)
+}
+
+/// Publish one batch of pending outbox items, returning the number of items handled
+async fn relay_batch<'a, SA, EA>(store: &'a SA, emitter: &'a EA) -> Result<usize, Error>
+where
+    SA: StoreAdapter,
+    EA: EmitterAdapter,
+{
+    let items = await!(store.pending_outbox(RELAY_BATCH_SIZE))?;
+
+    for item in items.iter() {
+        let payload = serde_json::to_vec(&item.payload)?;
+
+        match await!(emitter.publish(&item.routing_key, payload)) {
+            Ok(_) => {
+                debug!("Relayed event {} from outbox", item.event_id);
+
+                await!(store.mark_outbox_sent(item.event_id))?;
+            }
+            Err(e) => {
+                let retry_in = backoff(item.attempts);
+
+                warn!(
+                    "Failed to relay event {} from outbox, retrying in {}s: {}",
+                    item.event_id,
+                    retry_in.num_seconds(),
+                    e
+                );
+
+                await!(store.mark_outbox_failed(item.event_id, Utc::now() + retry_in))?;
+            }
+        }
+    }
+
+    Ok(items.len())
+}
+
+/// Publish events saved to the store's outbox through the emitter, forever
+///
+/// The outbox is checked every `poll_interval`, or straight away if the last check found a full
+/// batch of items. Items are only marked as sent once published, so they may be published more
+/// than once if the process stops in between.
+pub(crate) async fn relay_outbox<SA, EA>(store: SA, emitter: EA, poll_interval: Duration)
+where
+    SA: StoreAdapter,
+    EA: EmitterAdapter,
+{
+    loop {
+        match await!(relay_batch(&store, &emitter)) {
+            Ok(handled) if handled as i64 >= RELAY_BATCH_SIZE => continue,
+            Ok(_) => (),
+            Err(e) => error!("Outbox relay failed: {}", e),
+        }
+
+        if let Err(e) = await!(forward(Delay::new(Instant::now() + poll_interval))) {
+            error!("Outbox relay timer failed: {}", e);
+        }
+    }
+}
@@ -1,8 +1,11 @@
 //! Event store prelude
 
-pub use crate::aggregator::Aggregator;
+pub use crate::adapters::{CacheAdapter, EmitterAdapter, StoreAdapter};
+pub use crate::aggregator::{Aggregator, SnapshotPolicy};
+pub use crate::error::{Error, HandlerError};
 pub use crate::event::Event;
 pub use crate::event_context::EventContext;
 pub use crate::event_handler::EventHandler;
+pub use crate::event_query::EventQuery;
 pub use crate::store::Store;
 pub use crate::store_query::StoreQuery;
@@ -1,49 +1,152 @@
 use crate::adapters::{
-    AmqpEmitterAdapter, PgCacheAdapter, PgQuery, PgStoreAdapter, SaveResult, SaveStatus,
+    decode_events, CacheAdapter, EmitterAdapter, EventRecord, EventStream, SaveResult, SaveStatus,
+    SnapshotKey, StoreAdapter,
 };
-use crate::aggregator::Aggregator;
+use crate::aggregator::{cache_fingerprint, Aggregator};
+use crate::error::Error;
 use crate::event::Event;
+use crate::event_context::EventCause;
+use crate::event_query::EventQuery;
+use crate::outbox::relay_outbox;
 use crate::store_query::StoreQuery;
 use chrono::prelude::*;
 use event_store_derive_internals::EventData;
 use event_store_derive_internals::Events;
-use log::{debug, trace};
-use serde_json::Value as JsonValue;
+use log::{debug, info, trace};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{from_value, Value as JsonValue};
 use std::fmt::Debug;
-use std::io;
+use std::time::Duration;
+use tokio_async_await::stream::StreamExt;
 
 /// Event store that does not support subscriptions. Passed to [`crate::event_handler::EventHandler`] implementations.
 #[derive(Clone)]
-pub struct Store {
-    pub(crate) store: PgStoreAdapter,
-    cache: PgCacheAdapter,
-    emitter: AmqpEmitterAdapter,
+pub struct Store<SA, CA, EA> {
+    pub(crate) store: SA,
+    cache: CA,
+    emitter: EA,
+    cause: Option<EventCause>,
 }
 
-impl Store {
+/// An aggregate result or snapshot saved in the cache, tagged with the fingerprint of the aggregate
+/// type
+#[derive(serde_derive::Serialize, Debug)]
+struct CachedAggregate<'a, T> {
+    fingerprint: &'a str,
+    state: &'a T,
+}
+
+/// Decode a cached aggregate result or snapshot, discarding it if it was saved with a different
+/// fingerprint or can no longer be decoded
+fn decode_cached<T>(mut item: JsonValue, fingerprint: &str) -> Option<T>
+where
+    T: DeserializeOwned,
+{
+    if item["fingerprint"].as_str() != Some(fingerprint) {
+        debug!("Discarding cached aggregate with outdated fingerprint");
+
+        return None;
+    }
+
+    from_value(item["state"].take())
+        .map_err(|e| debug!("Discarding cached aggregate that failed to decode: {}", e))
+        .ok()
+}
+
+impl<SA, CA, EA> Store<SA, CA, EA>
+where
+    SA: StoreAdapter,
+    CA: CacheAdapter,
+    EA: EmitterAdapter,
+{
     /// Create a new non-subscribable store
-    pub fn new(store: PgStoreAdapter, cache: PgCacheAdapter, emitter: AmqpEmitterAdapter) -> Self {
+    pub fn new(store: SA, cache: CA, emitter: EA) -> Self {
         Self {
             store,
             cache,
             emitter,
+            cause: None,
+        }
+    }
+
+    /// A copy of this store that marks every event saved through it as caused by `event`
+    ///
+    /// Given to event handlers so that events they save can be traced back to the handled event.
+    pub(crate) fn caused_by<ED, S>(&self, event: &Event<ED, S>) -> Self {
+        Self {
+            cause: Some(EventCause::new(event.id, &event.context)),
+            ..self.clone()
         }
     }
 
+    /// Copy `event` with the correlation and causation IDs of the event being handled, if any,
+    /// keeping IDs the event already has
+    fn apply_cause<ED, S>(&self, event: &Event<ED, S>) -> Option<Event<ED, S>>
+    where
+        ED: Clone,
+        S: Clone,
+    {
+        self.cause.map(|cause| {
+            let mut caused = event.clone();
+
+            cause.apply_to_context(&mut caused.context);
+
+            caused
+        })
+    }
+
     /// Read events from the backing store, producing a reduced result
-    pub async fn aggregate<'a, T, QA, E>(&'a self, query_args: &'a QA) -> Result<T, io::Error>
+    ///
+    /// Events are streamed from the store and applied as they arrive, so aggregating a large
+    /// number of events doesn't require holding them all in memory. The aggregator's query can be
+    /// the store's own query type or anything that converts into it, such as an
+    /// [`crate::EventQuery`].
+    ///
+    /// If the aggregator has a [`crate::SnapshotPolicy`] and the query is limited to one stream,
+    /// aggregation starts from the latest snapshot of the stream if it's newer than the cached
+    /// result, and a new snapshot is saved every `frequency` events applied.
+    pub async fn aggregate<'a, T, QA, E, Q>(&'a self, query_args: &'a QA) -> Result<T, Error>
     where
-        E: Events,
-        T: Aggregator<E, QA, PgQuery>,
+        E: Events + Send,
+        T: Aggregator<E, QA, Q> + Send,
         QA: Clone + Debug + 'a,
+        Q: StoreQuery + Into<SA::Query>,
     {
         debug!("Aggregate with arguments {:?}", query_args);
 
-        let store_query = T::query(query_args.clone());
-        let cache_key = store_query.unique_id();
+        let query = T::query(query_args.clone());
+        let cache_key = query.unique_id();
+        let snapshots = T::SNAPSHOTS.and_then(|policy| {
+            query
+                .stream_id()
+                .map(|stream_id| (policy, String::from(stream_id)))
+        });
+        let store_query: SA::Query = query.into();
         let debug_cache_key = cache_key.clone();
 
-        let cache_result = await!(self.cache.read(&cache_key))?;
+        if let Some((policy, _)) = snapshots {
+            if policy.frequency == 0 {
+                return Err(Error::Configuration(format!(
+                    "Snapshot frequency of aggregate type {} must be greater than 0",
+                    policy.aggregate_type
+                )));
+            }
+        }
+
+        let snapshot_key = snapshots.as_ref().map(|(policy, stream_id)| SnapshotKey {
+            aggregate_type: policy.aggregate_type,
+            stream_id,
+            query_id: &cache_key,
+            version: policy.version,
+        });
+
+        let fingerprint = cache_fingerprint(&T::default(), T::CACHE_VERSION);
+
+        let cached = await!(self.cache.read::<JsonValue>(&cache_key))?;
+        let cache_result = cached.and_then(|(item, position)| {
+            decode_cached::<T>(item, &fingerprint).map(|state| (state, position))
+        });
 
         trace!(
             "Aggregate cache key {} result {:?}",
@@ -51,54 +154,274 @@ impl Store {
             cache_result
         );
 
-        let (initial_state, since) = cache_result
-            .map(|res| (res.0, Some(res.1)))
-            .unwrap_or_else(|| (T::default(), None));
+        let snapshot = match snapshot_key {
+            Some(key) => await!(self.cache.read_snapshot::<JsonValue>(key))?,
+            None => None,
+        };
+        let snapshot = snapshot.and_then(|(item, position)| {
+            decode_cached::<T>(item, &fingerprint).map(|state| (state, position))
+        });
+
+        trace!("Aggregate snapshot {:?}", snapshot);
+
+        // Start from whichever of the cached result and snapshot covers the most events
+        let (initial_state, after) = match (cache_result, snapshot) {
+            (Some(cached), Some(snapshot)) => {
+                if snapshot.1 > cached.1 {
+                    snapshot
+                } else {
+                    cached
+                }
+            }
+            (cached, snapshot) => cached.or(snapshot).unwrap_or_else(|| (T::default(), 0)),
+        };
 
         trace!(
-            "Aggregate initial state {:?}, since {:?}",
+            "Aggregate initial state {:?}, after position {}",
             initial_state,
-            since
+            after
         );
 
-        let events = await!(self.store.read(&store_query, since))?;
+        let mut events = self.store.stream_after::<E>(&store_query, after);
+
+        // Fold events as they're read so only one page of events is held in memory at a time
+        let mut result = initial_state;
+        let mut last_position = after;
+        let mut count: u64 = 0;
+        let mut since_snapshot = 0;
+
+        while let Some(event) = await!(events.next()) {
+            let event = event?;
 
-        trace!("Read {} events to aggregate", events.len());
+            if let Some(position) = event.position() {
+                last_position = last_position.max(position);
+            }
 
-        let result = events.iter().fold(initial_state, T::apply_event);
+            result = T::apply_event(result, &event);
+            count += 1;
+            since_snapshot += 1;
 
-        await!(self.cache.save(&cache_key, &result))?;
+            if let (Some((policy, _)), Some(key)) = (snapshots.as_ref(), snapshot_key) {
+                if since_snapshot >= policy.frequency {
+                    let snapshot = CachedAggregate {
+                        fingerprint: &fingerprint,
+                        state: &result,
+                    };
+
+                    await!(self.cache.save_snapshot(key, &snapshot, last_position))?;
+
+                    since_snapshot = 0;
+                }
+            }
+        }
+
+        trace!("Aggregated {} events", count);
+
+        let cached = CachedAggregate {
+            fingerprint: &fingerprint,
+            state: &result,
+        };
+
+        await!(self.cache.save(&cache_key, &cached, last_position))?;
 
         Ok(result)
     }
 
+    /// Read events matching `query` saved after the event at `position`, ordered by position
+    pub async fn read_after<'a, E>(
+        &'a self,
+        query: &'a SA::Query,
+        position: i64,
+    ) -> Result<Vec<E>, Error>
+    where
+        E: Events + Send + 'a,
+    {
+        await!(self.store.read_after(query, position))
+    }
+
+    /// Stream events matching `query` saved after the event at `position`, ordered by position
+    ///
+    /// Unlike [`Store::read_after`], events are read from the backing store a page at a time as
+    /// the stream is polled.
+    pub fn stream_after<'a, E>(&'a self, query: &'a SA::Query, position: i64) -> EventStream<'a, E>
+    where
+        E: Events + Send + 'a,
+    {
+        self.store.stream_after(query, position)
+    }
+
     /// Save an event and emit it to other subscribers
-    pub async fn save<'a, ED>(&'a self, event: &'a Event<ED>) -> SaveResult
+    ///
+    /// Events that already exist in the store are not emitted again.
+    pub async fn save<'a, ED, S>(&'a self, event: &'a Event<ED, S>) -> SaveResult
     where
-        ED: EventData + Debug,
+        ED: EventData + Clone + Debug,
+        S: Serialize + Clone + Debug,
     {
+        let caused = self.apply_cause(event);
+        let event = caused.as_ref().unwrap_or(event);
+
         debug!("Save and emit event {:?}", event);
 
-        self.store.save(&event)?;
+        let status = await!(self.store.save(event))?;
 
-        await!(self.emitter.emit(&event)).map(|_| SaveStatus::Ok)
+        // Duplicates were emitted when they were first saved
+        if status == SaveStatus::Duplicate || self.store.uses_outbox() {
+            return Ok(status);
+        }
+
+        await!(self.emitter.emit(event)).map(|_| status)
+    }
+
+    /// Save a batch of events in a single transaction, then emit them to other subscribers
+    ///
+    /// Either every event is saved or none are, and nothing is emitted until the batch is saved.
+    /// Events that already exist in the store are not emitted again. The result holds a status for
+    /// each event, in the same order as `events`.
+    pub async fn save_all<'a, E>(&'a self, events: &'a [E]) -> Result<Vec<SaveStatus>, Error>
+    where
+        E: Events,
+    {
+        debug!("Save and emit {} events", events.len());
+
+        let mut records = events
+            .iter()
+            .map(EventRecord::from_events)
+            .collect::<Result<Vec<EventRecord>, Error>>()?;
+
+        if let Some(cause) = self.cause {
+            for record in records.iter_mut() {
+                cause.apply(&mut record.context);
+            }
+        }
+
+        let statuses = await!(self.store.save_all(&records))?;
+
+        if self.store.uses_outbox() {
+            return Ok(statuses);
+        }
+
+        let saved = records
+            .iter()
+            .zip(statuses.iter())
+            .filter(|(_, status)| **status == SaveStatus::Ok);
+
+        for (record, _) in saved {
+            await!(self.emitter.emit_record(record))?;
+        }
+
+        Ok(statuses)
+    }
+
+    /// Save an event as the next event in its stream and emit it to other subscribers
+    ///
+    /// `expected_version` is the sequence number of the last event in the stream the caller
+    /// knows about, or 0 for a new stream. If another event has been saved to the stream since,
+    /// this fails with [`Error::Concurrency`] and the event is neither saved nor emitted.
+    pub async fn save_expecting<'a, ED, S>(
+        &'a self,
+        event: &'a Event<ED, S>,
+        expected_version: i64,
+    ) -> SaveResult
+    where
+        ED: EventData + Clone + Debug,
+        S: Serialize + Clone + Debug,
+    {
+        let caused = self.apply_cause(event);
+        let event = caused.as_ref().unwrap_or(event);
+
+        debug!(
+            "Save and emit event {:?} expecting stream version {}",
+            event, expected_version
+        );
+
+        let status = await!(self.store.save_expecting(event, expected_version))?;
+
+        // Duplicates were emitted when they were first saved
+        if status == SaveStatus::Duplicate || self.store.uses_outbox() {
+            return Ok(status);
+        }
+
+        await!(self.emitter.emit(event)).map(|_| status)
+    }
+
+    /// Get the sequence number of the last event in a stream, or 0 if the stream is empty
+    pub async fn stream_version<'a>(&'a self, stream_id: &'a str) -> Result<i64, Error> {
+        await!(self.store.stream_version(stream_id))
+    }
+
+    /// Start publishing events from the store's outbox through the emitter in a spawned task
+    ///
+    /// Only needed if the store adapter [uses an outbox](StoreAdapter::uses_outbox), in which case
+    /// saved events are not emitted until the relay publishes them. The outbox is checked every
+    /// `poll_interval`. Must be called from within a Tokio runtime.
+    ///
+    /// Only [`crate::adapters::PgStoreAdapter`] supports an outbox, enabled with
+    /// [`crate::adapters::PgStoreAdapter::with_outbox`]. For other adapters the relay does nothing.
+    pub fn start_outbox_relay(&self, poll_interval: Duration) {
+        info!("Starting outbox relay");
+
+        tokio::spawn_async(relay_outbox(
+            self.store.clone(),
+            self.emitter.clone(),
+            poll_interval,
+        ));
     }
 
     /// Emit an event to subscribers
-    pub async fn emit<'a, ED>(&'a self, event: &'a Event<ED>) -> Result<(), io::Error>
+    pub async fn emit<'a, ED, S>(&'a self, event: &'a Event<ED, S>) -> Result<(), Error>
     where
         ED: EventData,
+        S: Serialize,
     {
         await!(self.emitter.emit(event))
     }
 
-    /// Read all events since a given time
-    pub async fn read_events_since<'a>(
+    /// Read all events of type `ED` created at or after `since`, ordered by position
+    ///
+    /// Events that can't be decoded are handled according to the store adapter's decode policy.
+    pub async fn read_events_since<'a, ED>(
+        &'a self,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<Event<ED>>, Error>
+    where
+        ED: EventData + Send + 'a,
+    {
+        let rows =
+            await!(self
+                .store
+                .read_events_since(ED::event_namespace(), ED::event_type(), since))?;
+
+        decode_events(rows, self.store.decode_policy())
+    }
+
+    /// Read all events of any type in the events enum `E` created at or after `since`, ordered by
+    /// position
+    ///
+    /// Events that can't be decoded are handled according to the store adapter's decode policy.
+    pub async fn read_any_events_since<'a, E>(
+        &'a self,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<E>, Error>
+    where
+        E: Events + Send + 'a,
+        EventQuery: Into<SA::Query>,
+    {
+        let query = EventQuery::for_events::<E>().since(since).into();
+
+        await!(self.store.read(&query, None))
+    }
+
+    /// Read all events of a given namespace and type created at or after `since` as raw JSON,
+    /// ordered by position
+    ///
+    /// Intended for tools that handle events without knowing their types.
+    pub async fn read_raw_events_since<'a>(
         &'a self,
         event_namespace: &'a str,
         event_type: &'a str,
         since: DateTime<Utc>,
-    ) -> Result<Vec<JsonValue>, io::Error> {
+    ) -> Result<Vec<JsonValue>, Error> {
         await!(self
             .store
             .read_events_since(event_namespace, event_type, since))
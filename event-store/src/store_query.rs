@@ -6,4 +6,12 @@ pub trait StoreQuery {
     /// You must return a unique identifier based on the query you are performing. This identifier
     /// will then be used to identify the cache and optimize the aggregations using memoization
     fn unique_id(&self) -> String;
+
+    /// The ID of the stream this query is limited to, if it only matches events in one stream
+    ///
+    /// Aggregations over a single stream can start from a snapshot of the aggregate. Queries that
+    /// don't return a stream ID are always aggregated from their cached result or the beginning.
+    fn stream_id(&self) -> Option<&str> {
+        None
+    }
 }
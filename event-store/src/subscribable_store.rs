@@ -1,28 +1,42 @@
-use crate::adapters::{AmqpEmitterAdapter, PgCacheAdapter, PgQuery, PgStoreAdapter, SaveResult};
+use crate::adapters::{
+    CacheAdapter, EmitterAdapter, EmitterMessage, MessageStream, SaveResult, SaveStatus,
+    StoreAdapter,
+};
 use crate::aggregator::Aggregator;
+use crate::error::{Error, HandlerError};
 use crate::event::Event;
 use crate::event_handler::EventHandler;
+use crate::internals::forward;
 use crate::store::Store;
+use crate::store_query::StoreQuery;
 use event_store_derive_internals::EventData;
 use event_store_derive_internals::Events;
-use log::info;
+use log::{error, info, trace, warn};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
 use std::fmt::Debug;
-use std::io;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+use tokio_async_await::stream::StreamExt;
+
+/// Time to wait before requeueing a message whose handler asked to retry it
+const RETRY_DELAY: Duration = Duration::from_secs(1);
 
 /// The main event store struct
 #[derive(Clone)]
-pub struct SubscribableStore {
-    emitter: AmqpEmitterAdapter,
-    inner_store: Store,
+pub struct SubscribableStore<SA, CA, EA> {
+    emitter: EA,
+    inner_store: Store<SA, CA, EA>,
 }
 
-impl SubscribableStore {
+impl<SA, CA, EA> SubscribableStore<SA, CA, EA>
+where
+    SA: StoreAdapter,
+    CA: CacheAdapter,
+    EA: EmitterAdapter,
+{
     /// Create a new event store with the given store, cache and emitter adapters
-    pub fn new(
-        store: PgStoreAdapter,
-        cache: PgCacheAdapter,
-        emitter: AmqpEmitterAdapter,
-    ) -> Result<Self, io::Error> {
+    pub fn new(store: SA, cache: CA, emitter: EA) -> Result<Self, Error> {
         // TODO: Pass these in as refs to Store
         let inner_store = Store::new(store, cache, emitter.clone());
 
@@ -35,29 +49,61 @@ impl SubscribableStore {
     }
 
     /// Fetch an entity from the store by aggregating over matching events
-    pub async fn aggregate<'a, T, QA, E>(&'a self, query_args: &'a QA) -> Result<T, io::Error>
+    pub async fn aggregate<'a, T, QA, E, Q>(&'a self, query_args: &'a QA) -> Result<T, Error>
     where
-        E: Events,
-        T: Aggregator<E, QA, PgQuery>,
+        E: Events + Send,
+        T: Aggregator<E, QA, Q> + Send,
         QA: Clone + Debug + 'a,
+        Q: StoreQuery + Into<SA::Query>,
     {
-        let res: T = await!(self.inner_store.aggregate::<'a, T, QA, E>(&query_args))?;
+        let res: T = await!(self.inner_store.aggregate::<'a, T, QA, E, Q>(&query_args))?;
 
         Ok(res)
     }
 
     /// Save an event to the store, emitting it to other listeners
-    pub async fn save<'a, ED>(&'a self, event: &'a Event<ED>) -> SaveResult
+    pub async fn save<'a, ED, S>(&'a self, event: &'a Event<ED, S>) -> SaveResult
     where
-        ED: EventData + Debug,
+        ED: EventData + Clone + Debug,
+        S: Serialize + Clone + Debug,
     {
         await!(self.inner_store.save(event))
     }
 
+    /// Save a batch of events in a single transaction, emitting them to other listeners
+    ///
+    /// See [`Store::save_all`].
+    pub async fn save_all<'a, E>(&'a self, events: &'a [E]) -> Result<Vec<SaveStatus>, Error>
+    where
+        E: Events,
+    {
+        await!(self.inner_store.save_all(events))
+    }
+
+    /// Save an event as the next event in its stream, emitting it to other listeners
+    ///
+    /// See [`Store::save_expecting`].
+    pub async fn save_expecting<'a, ED, S>(
+        &'a self,
+        event: &'a Event<ED, S>,
+        expected_version: i64,
+    ) -> SaveResult
+    where
+        ED: EventData + Clone + Debug,
+        S: Serialize + Clone + Debug,
+    {
+        await!(self.inner_store.save_expecting(event, expected_version))
+    }
+
+    /// Get the sequence number of the last event in a stream, or 0 if the stream is empty
+    pub async fn stream_version<'a>(&'a self, stream_id: &'a str) -> Result<i64, Error> {
+        await!(self.inner_store.stream_version(stream_id))
+    }
+
     /// Subscribe to incoming events matching the namespace and type in `ED`
-    pub async fn subscribe<'a, ED>(&'a self) -> Result<(), io::Error>
+    pub async fn subscribe<'a, ED>(&'a self) -> Result<(), Error>
     where
-        ED: EventHandler + Debug + Send,
+        ED: EventHandler + Debug + Send + 'static,
     {
         info!(
             "Starting subscription to {}",
@@ -66,13 +112,119 @@ impl SubscribableStore {
 
         let inner_store = self.inner_store.clone();
 
-        await!(self.emitter.subscribe::<ED>(inner_store))
+        await!(self.emitter.subscribe::<ED, SA, CA>(inner_store))
+    }
+
+    /// Start publishing events from the store's outbox through the emitter in a spawned task
+    ///
+    /// See [`Store::start_outbox_relay`].
+    pub fn start_outbox_relay(&self, poll_interval: Duration) {
+        self.inner_store.start_outbox_relay(poll_interval)
     }
 
     // TODO: Can I do something clever with a trait impl here?
     /// Return a reference to the internal backing store. This is a dangerous method and should not
     /// be used in production code.
-    pub fn internals_get_store(&self) -> &Store {
+    pub fn internals_get_store(&self) -> &Store<SA, CA, EA> {
         &self.inner_store
     }
 }
+
+/// Decode messages received by an emitter and pass them to `ED`'s event handler
+///
+/// Messages are acked if the handler succeeds and requeued after [`RETRY_DELAY`] if it asks to
+/// retry. Messages that fail to decode, or that the handler failed to handle permanently, are
+/// discarded.
+pub(crate) async fn dispatch_messages<ED, SA, CA, EA>(
+    mut messages: MessageStream<EA::Message>,
+    store: Store<SA, CA, EA>,
+) where
+    ED: EventHandler + Debug + Send,
+    SA: StoreAdapter,
+    CA: CacheAdapter,
+    EA: EmitterAdapter,
+{
+    while let Some(Ok(message)) = await!(messages.next()) {
+        let parsed = serde_json::from_slice::<Event<ED>>(message.payload());
+
+        let result = match parsed {
+            Ok(event) => {
+                let event_id = event.id;
+
+                trace!("Received event {}", event_id);
+
+                // Events saved by the handler are marked as caused by this one
+                let handler_store = store.caused_by(&event);
+
+                match ED::handle_event(event, &handler_store) {
+                    Ok(_) => await!(message.ack()),
+                    Err(HandlerError::Retry(reason)) => {
+                        warn!(
+                            "Failed to handle event ID {}, requeueing in {:?}: {}",
+                            event_id, RETRY_DELAY, reason
+                        );
+
+                        // Requeued messages can be redelivered straight away, so wait before
+                        // requeueing instead of retrying in a tight loop. Other messages are
+                        // dispatched in the meantime.
+                        tokio::spawn_async(requeue_later(message, ED::event_namespace_and_type()));
+
+                        Ok(())
+                    }
+                    Err(HandlerError::Failed(reason)) => {
+                        error!(
+                            "Failed to handle event ID {}, discarding: {}",
+                            event_id, reason
+                        );
+
+                        await!(message.reject(false))
+                    }
+                }
+            }
+            Err(e) => {
+                trace!(
+                    "Failed event payload: {}",
+                    String::from_utf8(message.payload().to_vec())
+                        .unwrap_or(String::from("(failed to decode message)"))
+                );
+
+                let err = serde_json::from_slice::<JsonValue>(message.payload())
+                    .map(|evt| Error::decode(&evt, &e))
+                    .unwrap_or_else(|_| Error::Decode {
+                        id: None,
+                        event_namespace_and_type: Some(ED::event_namespace_and_type().into()),
+                        reason: e.to_string(),
+                    });
+
+                error!("{}", err);
+
+                await!(message.reject(false))
+            }
+        };
+
+        if let Err(e) = result {
+            error!(
+                "Could not acknowledge event {}: {}",
+                ED::event_namespace_and_type(),
+                e
+            );
+        }
+    }
+}
+
+/// Requeue a message after waiting for [`RETRY_DELAY`]
+async fn requeue_later<M>(message: M, event_namespace_and_type: &'static str)
+where
+    M: EmitterMessage,
+{
+    if let Err(e) = await!(forward(Delay::new(Instant::now() + RETRY_DELAY))) {
+        error!("Retry timer failed: {}", e);
+    }
+
+    if let Err(e) = await!(message.reject(true)) {
+        error!(
+            "Could not requeue event {}: {}",
+            event_namespace_and_type, e
+        );
+    }
+}
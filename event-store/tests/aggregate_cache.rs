@@ -0,0 +1,312 @@
+#![feature(await_macro, async_await)]
+#![feature(arbitrary_self_types)]
+
+use event_store::adapters::{
+    MemoryCacheAdapter, MemoryEmitterAdapter, MemoryStoreAdapter, SnapshotKey,
+};
+use event_store::internals::test_helpers::*;
+use event_store::prelude::*;
+use event_store::SubscribableStore;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+struct SnapshotCounter {
+    counter: i32,
+}
+
+impl Aggregator<TestEvents, String, EventQuery> for SnapshotCounter {
+    const SNAPSHOTS: Option<SnapshotPolicy> = Some(SnapshotPolicy {
+        aggregate_type: "snapshot_counter",
+        frequency: 2,
+        version: 2,
+    });
+
+    fn apply_event(acc: Self, event: &TestEvents) -> Self {
+        match event {
+            TestEvents::Inc(ref inc) => Self {
+                counter: acc.counter + inc.data.num,
+            },
+        }
+    }
+
+    fn query(stream_id: String) -> EventQuery {
+        EventQuery::for_events::<TestEvents>().with_stream_id(&stream_id)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+struct UnsnapshottableCounter {
+    counter: i32,
+}
+
+impl Aggregator<TestEvents, String, EventQuery> for UnsnapshottableCounter {
+    const SNAPSHOTS: Option<SnapshotPolicy> = Some(SnapshotPolicy {
+        aggregate_type: "unsnapshottable_counter",
+        frequency: 0,
+        version: 1,
+    });
+
+    fn apply_event(acc: Self, _event: &TestEvents) -> Self {
+        acc
+    }
+
+    fn query(stream_id: String) -> EventQuery {
+        EventQuery::for_events::<TestEvents>().with_stream_id(&stream_id)
+    }
+}
+
+fn counter_snapshot_key<'a>(
+    stream_id: &'a str,
+    query_id: &'a str,
+    version: u32,
+) -> SnapshotKey<'a> {
+    SnapshotKey {
+        aggregate_type: "snapshot_counter",
+        stream_id,
+        query_id,
+        version,
+    }
+}
+
+fn stream_event(num: i32, stream_id: &str) -> Event<TestEvent> {
+    Event::from_data(TestEvent { num }).with_stream_id(stream_id)
+}
+
+#[test]
+fn aggregate_resumes_after_cached_position() {
+    let _ = pretty_env_logger::try_init();
+
+    let (initial, resumed, cached) = run_async(async {
+        let store = SubscribableStore::new(
+            MemoryStoreAdapter::new(),
+            MemoryCacheAdapter::new(),
+            MemoryEmitterAdapter::new(),
+        )?;
+
+        let arg = String::new();
+
+        let first = Event::from_data(TestEvent { num: 1 });
+
+        await!(store.save(&first))?;
+
+        let initial: TestCounterEntity = await!(store.aggregate(&arg))?;
+
+        // Saved after the cache was written but timestamped before it
+        let mut backdated = Event::from_data(TestEvent { num: 10 });
+        backdated.context.time = first.context.time - chrono::Duration::seconds(60);
+
+        let latest = Event::from_data(TestEvent { num: 100 });
+
+        await!(store.save(&backdated))?;
+        await!(store.save(&latest))?;
+
+        let resumed: TestCounterEntity = await!(store.aggregate(&arg))?;
+        let cached: TestCounterEntity = await!(store.aggregate(&arg))?;
+
+        Ok((initial, resumed, cached))
+    })
+    .unwrap();
+
+    assert_eq!(initial, TestCounterEntity { counter: 1 });
+    assert_eq!(resumed, TestCounterEntity { counter: 111 });
+    assert_eq!(cached, TestCounterEntity { counter: 111 });
+}
+
+#[test]
+fn discard_outdated_cache() {
+    let _ = pretty_env_logger::try_init();
+
+    let (untagged, outdated, cached) = run_async(async {
+        let cache = MemoryCacheAdapter::new();
+
+        let store = SubscribableStore::new(
+            MemoryStoreAdapter::new(),
+            cache.clone(),
+            MemoryEmitterAdapter::new(),
+        )?;
+
+        let first = Event::from_data(TestEvent { num: 1 });
+        let second = Event::from_data(TestEvent { num: 2 });
+
+        await!(store.save(&first))?;
+        await!(store.save(&second))?;
+
+        let key = EventQuery::for_events::<TestEvents>().unique_id();
+        let arg = String::new();
+
+        // Results cached before fingerprints were recorded, or by a different shape of the
+        // aggregate, are recomputed
+        await!(cache.save(&key, &TestCounterEntity { counter: 1000 }, 2))?;
+
+        let untagged: TestCounterEntity = await!(store.aggregate(&arg))?;
+
+        let outdated = json!({ "fingerprint": "outdated", "state": { "counter": 1000 } });
+
+        await!(cache.save(&key, &outdated, 2))?;
+
+        let outdated: TestCounterEntity = await!(store.aggregate(&arg))?;
+
+        let cached = await!(cache.read::<JsonValue>(&key))?;
+
+        Ok((untagged, outdated, cached))
+    })
+    .unwrap();
+
+    assert_eq!(untagged, TestCounterEntity { counter: 3 });
+    assert_eq!(outdated, TestCounterEntity { counter: 3 });
+
+    let (item, position) = cached.unwrap();
+
+    assert_eq!(item["state"], json!({ "counter": 3 }));
+    assert!(item["fingerprint"].is_string());
+    assert_eq!(position, 2);
+}
+
+#[test]
+fn aggregate_from_snapshot() {
+    let _ = pretty_env_logger::try_init();
+
+    let (counter, other, snapshot) = run_async(async {
+        let cache = MemoryCacheAdapter::new();
+
+        let store = SubscribableStore::new(
+            MemoryStoreAdapter::new(),
+            cache.clone(),
+            MemoryEmitterAdapter::new(),
+        )?;
+
+        for num in 1..=5 {
+            let event = stream_event(num, "counter-1");
+
+            await!(store.save(&event))?;
+        }
+
+        let other_event = stream_event(100, "counter-2");
+
+        await!(store.save(&other_event))?;
+
+        let stream_id = String::from("counter-1");
+        let other_stream_id = String::from("counter-2");
+
+        let counter: SnapshotCounter = await!(store.aggregate(&stream_id))?;
+
+        let query_id = SnapshotCounter::query(stream_id.clone()).unique_id();
+        let other_query_id = SnapshotCounter::query(other_stream_id.clone()).unique_id();
+
+        let key = counter_snapshot_key("counter-1", &query_id, 2);
+        let snapshot = await!(cache.read_snapshot::<JsonValue>(key))?;
+
+        // Snapshots from an older version of the aggregate, or over a different query, must be
+        // ignored
+        let (mut planted, _) = snapshot.clone().unwrap();
+        planted["state"]["counter"] = json!(1000);
+
+        let old_version = counter_snapshot_key("counter-2", &other_query_id, 1);
+        let other_query = counter_snapshot_key("counter-2", "other-query", 2);
+
+        await!(cache.save_snapshot(old_version, &planted, 1))?;
+        await!(cache.save_snapshot(other_query, &planted, 1))?;
+
+        let other: SnapshotCounter = await!(store.aggregate(&other_stream_id))?;
+
+        Ok((counter, other, snapshot))
+    })
+    .unwrap();
+
+    assert_eq!(counter, SnapshotCounter { counter: 15 });
+    assert_eq!(other, SnapshotCounter { counter: 100 });
+
+    // Snapshots are saved after every second event, so the latest covers the first four
+    let (snapshot, position) = snapshot.unwrap();
+
+    assert_eq!(snapshot["state"], json!({ "counter": 10 }));
+    assert_eq!(position, 4);
+}
+
+#[test]
+fn aggregate_starts_from_snapshot() {
+    let _ = pretty_env_logger::try_init();
+
+    let counter = run_async(async {
+        let events = MemoryStoreAdapter::new();
+        let cache = MemoryCacheAdapter::new();
+
+        let store =
+            SubscribableStore::new(events.clone(), cache.clone(), MemoryEmitterAdapter::new())?;
+
+        for num in 1..=3 {
+            let event = stream_event(num, "counter-1");
+
+            await!(store.save(&event))?;
+        }
+
+        let stream_id = String::from("counter-1");
+        let query_id = SnapshotCounter::query(stream_id.clone()).unique_id();
+
+        let _: SnapshotCounter = await!(store.aggregate(&stream_id))?;
+
+        let key = counter_snapshot_key("counter-1", &query_id, 2);
+        let (mut snapshot, _) = await!(cache.read_snapshot::<JsonValue>(key))?.unwrap();
+
+        // Only the event after the snapshot's position should be applied on top of it, so start
+        // from a fresh cache holding just the modified snapshot
+        snapshot["state"]["counter"] = json!(100);
+
+        let cache = MemoryCacheAdapter::new();
+
+        await!(cache.save_snapshot(key, &snapshot, 2))?;
+
+        let store = SubscribableStore::new(events, cache, MemoryEmitterAdapter::new())?;
+
+        let counter: SnapshotCounter = await!(store.aggregate(&stream_id))?;
+
+        Ok(counter)
+    })
+    .unwrap();
+
+    assert_eq!(counter, SnapshotCounter { counter: 103 });
+}
+
+#[test]
+fn snapshot_never_rolls_back() {
+    let _ = pretty_env_logger::try_init();
+
+    let snapshot = run_async(async {
+        let cache = MemoryCacheAdapter::new();
+        let key = counter_snapshot_key("counter-1", "query", 2);
+
+        let newer = json!({ "counter": 10 });
+        let older = json!({ "counter": 3 });
+
+        await!(cache.save_snapshot(key, &newer, 4))?;
+        await!(cache.save_snapshot(key, &older, 2))?;
+
+        await!(cache.read_snapshot::<JsonValue>(key))
+    })
+    .unwrap();
+
+    assert_eq!(snapshot, Some((json!({ "counter": 10 }), 4)));
+}
+
+#[test]
+fn reject_zero_snapshot_frequency() {
+    let _ = pretty_env_logger::try_init();
+
+    let result = run_async(async {
+        let store = SubscribableStore::new(
+            MemoryStoreAdapter::new(),
+            MemoryCacheAdapter::new(),
+            MemoryEmitterAdapter::new(),
+        )?;
+
+        let stream_id = String::from("counter-1");
+
+        await!(store.aggregate::<UnsnapshottableCounter, _, _, _>(&stream_id))
+    });
+
+    assert!(match result {
+        Err(Error::Configuration(_)) => true,
+        _ => false,
+    });
+}
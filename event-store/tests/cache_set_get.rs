@@ -1,18 +1,15 @@
 #![feature(await_macro, async_await)]
 #![feature(arbitrary_self_types)]
 
-use event_store::adapters::{CacheResult, PgCacheAdapter};
-use event_store::internals::{backward, test_helpers::*};
-use futures::future::Future;
+use event_store::adapters::{CacheAdapter, CacheResult, PgCacheAdapter, RedisCacheAdapter};
+use event_store::internals::test_helpers::*;
 use log::trace;
-use std::io;
-use tokio::runtime::Runtime;
 
 #[test]
 fn cache_set_get() {
-    pretty_env_logger::init();
+    let _ = pretty_env_logger::try_init();
 
-    let fut = backward(async {
+    let res: Option<CacheResult<TestCounterEntity>> = run_async(async {
         let test_entity = TestCounterEntity { counter: 100 };
 
         trace!("Save and emit test");
@@ -21,17 +18,39 @@ fn cache_set_get() {
 
         let cache = await!(PgCacheAdapter::new(conn.clone()))?;
 
-        await!(cache.save("_test".into(), &test_entity))?;
+        await!(cache.save("_test".into(), &test_entity, 1))?;
 
         let res = await!(cache.read::<TestCounterEntity>("_test".into()))?;
 
         Ok(res)
     })
-    // Required so Rust can figure out what type `E` is
-    .map_err(|e: io::Error| e);
+    .unwrap();
 
-    let res: Option<CacheResult<TestCounterEntity>> =
-        Runtime::new().unwrap().block_on(fut).unwrap();
+    assert_eq!(res.unwrap().0, TestCounterEntity { counter: 100 });
+}
+
+#[test]
+fn redis_cache_set_get() {
+    let _ = pretty_env_logger::try_init();
+
+    let res: Option<CacheResult<TestCounterEntity>> = run_async(async {
+        let test_entity = TestCounterEntity { counter: 100 };
+
+        let client = redis::Client::open("redis://localhost:6378")?;
+
+        let cache = await!(RedisCacheAdapter::new(client))?;
+
+        let missing = await!(cache.read::<TestCounterEntity>("_test_missing".into()))?;
+
+        assert!(missing.is_none());
+
+        await!(cache.save("_test".into(), &test_entity, 1))?;
+
+        let res = await!(cache.read::<TestCounterEntity>("_test".into()))?;
+
+        Ok(res)
+    })
+    .unwrap();
 
     assert_eq!(res.unwrap().0, TestCounterEntity { counter: 100 });
 }
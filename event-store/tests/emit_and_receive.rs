@@ -1,22 +1,49 @@
 #![feature(await_macro, async_await)]
 #![feature(arbitrary_self_types)]
 
-use event_store::adapters::{AmqpEmitterAdapter, PgCacheAdapter, PgStoreAdapter};
-use event_store::internals::{backward, forward, test_helpers::*};
+use event_store::adapters::{
+    AmqpEmitterAdapter, MemoryCacheAdapter, MemoryEmitterAdapter, MemoryStoreAdapter,
+    PgCacheAdapter, PgEmitterAdapter, PgStoreAdapter, SaveStatus,
+};
+use event_store::internals::{forward, test_helpers::*};
 use event_store::prelude::*;
 use event_store::SubscribableStore;
-use futures::future::Future;
+use event_store_derive::*;
 use log::info;
-use std::io;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
-use tokio::runtime::Runtime;
 use tokio::timer::Delay;
 
+static RECEIVED_TOTAL: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(EventData, Debug, Clone)]
+#[event_store(namespace = "memory_test")]
+struct CountedEvent {
+    pub num: usize,
+}
+
+impl EventHandler for CountedEvent {
+    fn handle_event<SA, CA, EA>(
+        event: Event<Self>,
+        _store: &Store<SA, CA, EA>,
+    ) -> Result<(), HandlerError>
+    where
+        SA: StoreAdapter,
+        CA: CacheAdapter,
+        EA: EmitterAdapter,
+    {
+        RECEIVED_TOTAL.fetch_add(event.data.num, Ordering::SeqCst);
+
+        Ok(())
+    }
+}
+
 #[test]
 fn emit_and_receive() {
-    pretty_env_logger::init();
+    let _ = pretty_env_logger::try_init();
 
-    let fut = backward(async {
+    run_async(async {
         let test_event = Event::from_data(TestEvent { num: 100 });
 
         info!("Save and emit test");
@@ -67,8 +94,172 @@ fn emit_and_receive() {
 
         Ok(())
     })
-    // Required so Rust can figure out what type `E` is
-    .map_err(|e: io::Error| e);
+    .unwrap();
+}
+
+#[test]
+fn pg_emit_and_receive() {
+    let _ = pretty_env_logger::try_init();
+
+    run_async(async {
+        let test_event = Event::from_data(TestEvent { num: 100 });
+
+        info!("Save and notify test");
+
+        let url = pg_create_random_db_url(Some("pg_emit_and_receive"));
+        let pool = r2d2::Pool::new(pg_connection_manager(&url)).unwrap();
+
+        let sender_store = SubscribableStore::new(
+            await!(PgStoreAdapter::new(pool.clone()))?,
+            await!(PgCacheAdapter::new(pool.clone()))?,
+            await!(PgEmitterAdapter::new(
+                pool.clone(),
+                pg_connection_manager(&url)
+            ))?,
+        )?;
+
+        let receiver_store = SubscribableStore::new(
+            await!(PgStoreAdapter::new(pool.clone()))?,
+            await!(PgCacheAdapter::new(pool.clone()))?,
+            await!(PgEmitterAdapter::new(
+                pool.clone(),
+                pg_connection_manager(&url)
+            ))?,
+        )?;
+
+        await!(receiver_store.subscribe::<TestEvent>())?;
+
+        await!(sender_store.save(&test_event))?;
+
+        // Wait for event to be received before freeing everything
+        await!(forward(Delay::new(
+            Instant::now() + Duration::from_millis(100)
+        )))
+        .unwrap();
+
+        let arg = &String::new();
+        let result: TestCounterEntity = await!(receiver_store.aggregate(arg))?;
+
+        assert_eq!(result, TestCounterEntity { counter: 100 });
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn pg_outbox_relay() {
+    let _ = pretty_env_logger::try_init();
+
+    run_async(async {
+        let test_event = Event::from_data(TestEvent { num: 100 });
+
+        let url = pg_create_random_db_url(Some("pg_outbox_relay"));
+        let pool = r2d2::Pool::new(pg_connection_manager(&url)).unwrap();
+
+        let store = SubscribableStore::new(
+            await!(PgStoreAdapter::new(pool.clone()))?.with_outbox(),
+            await!(PgCacheAdapter::new(pool.clone()))?,
+            await!(PgEmitterAdapter::new(
+                pool.clone(),
+                pg_connection_manager(&url)
+            ))?,
+        )?;
+
+        let is_sent = || -> Result<bool, Error> {
+            let rows = pool.get()?.query(
+                "select sent_at is not null from events_outbox where event_id = $1",
+                &[&test_event.id],
+            )?;
+
+            Ok(rows.get(0).get(0))
+        };
+
+        await!(store.save(&test_event))?;
+
+        assert_eq!(is_sent()?, false);
+
+        store.start_outbox_relay(Duration::from_millis(10));
+
+        // Give the relay time to publish the event
+        await!(forward(Delay::new(
+            Instant::now() + Duration::from_millis(100)
+        )))
+        .unwrap();
+
+        assert_eq!(is_sent()?, true);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn pg_outbox_two_relays() {
+    let _ = pretty_env_logger::try_init();
+
+    let (first_claim, second_claim) = run_async(async {
+        let test_event = Event::from_data(TestEvent { num: 100 });
+
+        let pool = pg_create_random_db(Some("pg_outbox_two_relays"));
+
+        let first_relay = await!(PgStoreAdapter::new(pool.clone()))?.with_outbox();
+        let second_relay = await!(PgStoreAdapter::new(pool.clone()))?.with_outbox();
+
+        await!(first_relay.save(&test_event))?;
+
+        // Each relay claims a batch before publishing it, so the second must not see the item
+        // claimed by the first
+        let first_claim = await!(first_relay.pending_outbox(10))?;
+        let second_claim = await!(second_relay.pending_outbox(10))?;
+
+        Ok((first_claim, second_claim))
+    })
+    .unwrap();
+
+    assert_eq!(first_claim.len(), 1);
+    assert_eq!(second_claim.len(), 0);
+}
+
+#[test]
+fn memory_emit_and_receive() {
+    let _ = pretty_env_logger::try_init();
+
+    run_async(async {
+        let emitter = MemoryEmitterAdapter::new();
+
+        let sender_store = SubscribableStore::new(
+            MemoryStoreAdapter::new(),
+            MemoryCacheAdapter::new(),
+            emitter.clone(),
+        )?;
+
+        let receiver_store = SubscribableStore::new(
+            MemoryStoreAdapter::new(),
+            MemoryCacheAdapter::new(),
+            emitter,
+        )?;
+
+        await!(receiver_store.subscribe::<CountedEvent>())?;
+
+        let first = Event::from_data(CountedEvent { num: 10 });
+        let second = Event::from_data(CountedEvent { num: 5 });
+
+        await!(sender_store.save(&first))?;
+        await!(sender_store.save(&second))?;
+
+        // Saving the same event again must not emit it a second time
+        assert_eq!(await!(sender_store.save(&first))?, SaveStatus::Duplicate);
+
+        // Give the spawned subscriber time to handle both events
+        await!(forward(Delay::new(
+            Instant::now() + Duration::from_millis(100)
+        )))
+        .unwrap();
+
+        Ok(())
+    })
+    .unwrap();
 
-    Runtime::new().unwrap().block_on(fut).unwrap();
+    assert_eq!(RECEIVED_TOTAL.load(Ordering::SeqCst), 15);
 }
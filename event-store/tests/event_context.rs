@@ -0,0 +1,141 @@
+#![feature(await_macro, async_await)]
+#![feature(arbitrary_self_types)]
+
+use event_store::adapters::{MemoryCacheAdapter, MemoryEmitterAdapter, MemoryStoreAdapter};
+use event_store::internals::{forward, test_helpers::*};
+use event_store::prelude::*;
+use event_store::SubscribableStore;
+use event_store_derive::*;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+
+#[derive(EventData, Debug, Clone)]
+#[event_store(namespace = "memory_test")]
+struct CausingEvent {
+    pub num: i32,
+}
+
+impl EventHandler for CausingEvent {
+    fn handle_event<SA, CA, EA>(
+        event: Event<Self>,
+        store: &Store<SA, CA, EA>,
+    ) -> Result<(), HandlerError>
+    where
+        SA: StoreAdapter,
+        CA: CacheAdapter,
+        EA: EmitterAdapter,
+    {
+        let store = store.clone();
+
+        tokio::spawn_async(async move {
+            let caused = Event::from_data(TestEvent {
+                num: event.data.num,
+            });
+
+            if let Err(e) = await!(store.save(&caused)) {
+                panic!("Failed to save caused event: {}", e);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct UserSubject {
+    user_id: String,
+    org_id: String,
+}
+
+#[test]
+fn handler_events_inherit_correlation() {
+    let _ = pretty_env_logger::try_init();
+
+    let (first_id, second_id, mut caused) = run_async(async {
+        let emitter = MemoryEmitterAdapter::new();
+        let adapter = MemoryStoreAdapter::new();
+
+        let sender_store = SubscribableStore::new(
+            MemoryStoreAdapter::new(),
+            MemoryCacheAdapter::new(),
+            emitter.clone(),
+        )?;
+
+        let receiver_store =
+            SubscribableStore::new(adapter.clone(), MemoryCacheAdapter::new(), emitter)?;
+
+        await!(receiver_store.subscribe::<CausingEvent>())?;
+
+        let since = chrono::Utc::now();
+
+        let first = Event::from_data(CausingEvent { num: 1 });
+        let second = Event::from_data(CausingEvent { num: 2 }).caused_by(&first);
+
+        await!(sender_store.save(&first))?;
+        await!(sender_store.save(&second))?;
+
+        // Give the spawned subscriber time to handle both events
+        await!(forward(Delay::new(
+            Instant::now() + Duration::from_millis(100)
+        )))
+        .unwrap();
+
+        let caused = await!(receiver_store
+            .internals_get_store()
+            .read_events_since::<TestEvent>(since))?;
+
+        Ok((first.id, second.id, caused))
+    })
+    .unwrap();
+
+    caused.sort_by_key(|event| event.data.num);
+
+    let ids = caused
+        .iter()
+        .map(|event| (event.context.correlation_id, event.context.causation_id))
+        .collect::<Vec<_>>();
+
+    // Both events belong to the workflow started by the first event
+    assert_eq!(
+        ids,
+        vec![
+            (Some(first_id), Some(first_id)),
+            (Some(first_id), Some(second_id)),
+        ]
+    );
+}
+
+#[test]
+fn empty_subject_is_none() {
+    let context = json!({
+        "action": null,
+        "subject": {},
+        "time": "2019-04-03T13:40:55.901Z"
+    });
+
+    let typed: EventContext<UserSubject> = serde_json::from_value(context.clone()).unwrap();
+    let untyped: EventContext = serde_json::from_value(context).unwrap();
+
+    assert_eq!(typed.subject, None);
+    assert_eq!(untyped.subject, None);
+
+    // Missing subjects are written back in the legacy shape
+    assert_eq!(serde_json::to_value(typed).unwrap()["subject"], json!({}));
+
+    let populated: EventContext<UserSubject> = serde_json::from_value(json!({
+        "action": null,
+        "subject": { "user_id": "alice", "org_id": "org-1" },
+        "time": "2019-04-03T13:40:55.901Z"
+    }))
+    .unwrap();
+
+    assert_eq!(
+        populated.subject,
+        Some(UserSubject {
+            user_id: "alice".into(),
+            org_id: "org-1".into(),
+        })
+    );
+}
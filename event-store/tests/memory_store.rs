@@ -0,0 +1,515 @@
+#![feature(await_macro, async_await)]
+#![feature(arbitrary_self_types)]
+
+use event_store::adapters::{
+    DecodePolicy, DecodeReport, MemoryCacheAdapter, MemoryEmitterAdapter, MemoryQuery,
+    MemoryStoreAdapter, SaveStatus,
+};
+use event_store::internals::{forward, test_helpers::*};
+use event_store::prelude::*;
+use event_store::SubscribableStore;
+use event_store_derive::*;
+use futures::Stream;
+use log::trace;
+use serde_derive::{Deserialize, Serialize};
+
+/// An event that isn't part of `TestEvents`
+#[derive(EventData, Debug, Clone)]
+#[event_store(namespace = "memory_test")]
+struct OtherEvent {
+    pub num: i32,
+}
+
+#[test]
+fn memory_save_and_aggregate() {
+    let _ = pretty_env_logger::try_init();
+
+    let (uncached_result, cached_result) = run_async(async {
+        let test_event = Event::from_data(TestEvent { num: 100 });
+        let test_event_2 = Event::from_data(TestEvent { num: 200 });
+
+        trace!("Memory save and aggregate test");
+
+        let store = SubscribableStore::new(
+            MemoryStoreAdapter::new(),
+            MemoryCacheAdapter::new(),
+            MemoryEmitterAdapter::new(),
+        )?;
+
+        await!(store.save(&test_event))?;
+        await!(store.save(&test_event_2))?;
+
+        let arg = String::new();
+
+        let uncached_result: TestCounterEntity = await!(store.aggregate(&arg))?;
+
+        let cached_result: TestCounterEntity = await!(store.aggregate(&arg))?;
+
+        Ok((uncached_result, cached_result))
+    })
+    .unwrap();
+
+    assert_eq!(uncached_result, TestCounterEntity { counter: 300i32 });
+    assert_eq!(uncached_result, cached_result);
+}
+
+#[test]
+fn memory_decode_policy() {
+    let _ = pretty_env_logger::try_init();
+
+    let (decoded, failures) = run_async(async {
+        let report = DecodeReport::new();
+
+        let failing = MemoryStoreAdapter::new();
+        let collecting = failing
+            .clone()
+            .with_decode_policy(DecodePolicy::Collect(report.clone()));
+
+        // `OtherEvent` isn't part of `TestEvents` so can't be decoded when reading them
+        let test_event = Event::from_data(TestEvent { num: 1 });
+        let other_event = Event::from_data(OtherEvent { num: 2 });
+
+        await!(failing.save(&test_event))?;
+        await!(failing.save(&other_event))?;
+
+        let query = MemoryQuery::all();
+
+        let failed = await!(failing.read::<TestEvents>(&query, None));
+
+        assert!(match failed {
+            Err(Error::Decode { .. }) => true,
+            _ => false,
+        });
+
+        let events = await!(collecting.read::<TestEvents>(&query, None))?;
+
+        Ok((events.len(), report.take()))
+    })
+    .unwrap();
+
+    assert_eq!(decoded, 1);
+    assert_eq!(failures.len(), 1);
+}
+
+#[test]
+fn memory_save_expecting() {
+    let _ = pretty_env_logger::try_init();
+
+    let version = run_async(async {
+        let store = SubscribableStore::new(
+            MemoryStoreAdapter::new(),
+            MemoryCacheAdapter::new(),
+            MemoryEmitterAdapter::new(),
+        )?;
+
+        let stream_id = "counter-1";
+
+        let version = await!(store.stream_version(stream_id))?;
+
+        assert_eq!(version, 0);
+
+        let first = Event::from_data(TestEvent { num: 1 }).with_stream_id(stream_id);
+        let second = Event::from_data(TestEvent { num: 2 }).with_stream_id(stream_id);
+        let stale = Event::from_data(TestEvent { num: 3 }).with_stream_id(stream_id);
+
+        await!(store.save_expecting(&first, version))?;
+        await!(store.save_expecting(&second, version + 1))?;
+
+        // Another writer saved `second` after `version` was read
+        let conflict = await!(store.save_expecting(&stale, version + 1));
+
+        assert!(match conflict {
+            Err(Error::Concurrency(_)) => true,
+            _ => false,
+        });
+
+        await!(store.stream_version(stream_id))
+    })
+    .unwrap();
+
+    assert_eq!(version, 2);
+}
+
+#[test]
+fn memory_save_expecting_checks_version() {
+    let _ = pretty_env_logger::try_init();
+
+    let version = run_async(async {
+        let adapter = MemoryStoreAdapter::new();
+
+        let stream_id = "counter-1";
+
+        let first = Event::from_data(TestEvent { num: 1 }).with_stream_id(stream_id);
+        let ahead = Event::from_data(TestEvent { num: 2 }).with_stream_id(stream_id);
+        let mut third = Event::from_data(TestEvent { num: 3 }).with_stream_id(stream_id);
+        let stale = Event::from_data(TestEvent { num: 4 }).with_stream_id(stream_id);
+
+        await!(adapter.save_expecting(&first, 0))?;
+
+        // The stream is at version 1, not 2
+        let ahead_result = await!(adapter.save_expecting(&ahead, 2));
+
+        assert!(match ahead_result {
+            Err(Error::Concurrency(_)) => true,
+            _ => false,
+        });
+
+        // Leave a gap at sequence number 2
+        third.sequence = Some(3);
+
+        await!(adapter.save(&third))?;
+
+        // A writer that read version 1 must not fill the gap
+        let stale_result = await!(adapter.save_expecting(&stale, 1));
+
+        assert!(match stale_result {
+            Err(Error::Concurrency(_)) => true,
+            _ => false,
+        });
+
+        await!(adapter.stream_version(stream_id))
+    })
+    .unwrap();
+
+    assert_eq!(version, 3);
+}
+
+#[test]
+fn memory_read_after_position() {
+    let _ = pretty_env_logger::try_init();
+
+    let (all, after) = run_async(async {
+        let adapter = MemoryStoreAdapter::new();
+
+        let first = Event::from_data(TestEvent { num: 1 });
+        let mut second = Event::from_data(TestEvent { num: 2 });
+        let mut third = Event::from_data(TestEvent { num: 3 });
+
+        // Backdated events are still read in the order they were saved
+        second.context.time = first.context.time - chrono::Duration::seconds(10);
+        third.context.time = first.context.time - chrono::Duration::seconds(20);
+
+        await!(adapter.save(&first))?;
+        await!(adapter.save(&second))?;
+        await!(adapter.save(&third))?;
+
+        let query = MemoryQuery::all();
+
+        let all: Vec<TestEvents> = await!(adapter.read(&query, None))?;
+        let after: Vec<TestEvents> = await!(adapter.read_after(&query, 1))?;
+
+        Ok((all, after))
+    })
+    .unwrap();
+
+    let nums = |events: &Vec<TestEvents>| {
+        events
+            .iter()
+            .map(|event| match event {
+                TestEvents::Inc(event) => (event.position, event.data.num),
+            })
+            .collect::<Vec<(Option<i64>, i32)>>()
+    };
+
+    assert_eq!(nums(&all), vec![(Some(1), 1), (Some(2), 2), (Some(3), 3)]);
+    assert_eq!(nums(&after), vec![(Some(2), 2), (Some(3), 3)]);
+}
+
+#[test]
+fn memory_save_all() {
+    let _ = pretty_env_logger::try_init();
+
+    let result: TestCounterEntity = run_async(async {
+        let store = SubscribableStore::new(
+            MemoryStoreAdapter::new(),
+            MemoryCacheAdapter::new(),
+            MemoryEmitterAdapter::new(),
+        )?;
+
+        let existing = Event::from_data(TestEvent { num: 1 });
+
+        await!(store.save(&existing))?;
+
+        let batch = [
+            TestEvents::Inc(Event::from_data(TestEvent { num: 10 })),
+            TestEvents::Inc(existing),
+        ];
+
+        let statuses = await!(store.save_all(&batch))?;
+
+        assert_eq!(statuses, vec![SaveStatus::Ok, SaveStatus::Duplicate]);
+
+        let mut first = Event::from_data(TestEvent { num: 100 }).with_stream_id("counter-1");
+        let mut conflicting = Event::from_data(TestEvent { num: 1000 }).with_stream_id("counter-1");
+        first.sequence = Some(1);
+        conflicting.sequence = Some(1);
+
+        // The whole batch fails, so `first` is not saved either
+        let batch = [TestEvents::Inc(first), TestEvents::Inc(conflicting)];
+
+        let conflict = await!(store.save_all(&batch));
+
+        assert!(match conflict {
+            Err(Error::Concurrency(_)) => true,
+            _ => false,
+        });
+
+        let arg = String::new();
+
+        await!(store.aggregate(&arg))
+    })
+    .unwrap();
+
+    assert_eq!(result, TestCounterEntity { counter: 11 });
+}
+
+#[test]
+fn memory_duplicate_verification() {
+    let _ = pretty_env_logger::try_init();
+
+    run_async(async {
+        let store = MemoryStoreAdapter::new().with_duplicate_verification();
+
+        let event = Event::from_data(TestEvent { num: 1 });
+
+        await!(store.save(&event))?;
+
+        assert_eq!(await!(store.save(&event))?, SaveStatus::Duplicate);
+
+        let changed = Event::from_data(TestEvent { num: 2 }).with_id(event.id);
+
+        let result = await!(store.save(&changed));
+
+        assert!(match result {
+            Err(Error::Duplicate(id)) => id == event.id,
+            _ => false,
+        });
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn memory_aggregate_over_several_pages() {
+    let _ = pretty_env_logger::try_init();
+
+    let result = run_async(async {
+        let store = SubscribableStore::new(
+            MemoryStoreAdapter::new(),
+            MemoryCacheAdapter::new(),
+            MemoryEmitterAdapter::new(),
+        )?;
+
+        // More events than are read from the store in one page
+        let events = (0..2500)
+            .map(|_| TestEvents::Inc(Event::from_data(TestEvent { num: 1 })))
+            .collect::<Vec<TestEvents>>();
+
+        await!(store.save_all(&events))?;
+
+        let query = MemoryQuery::all();
+
+        let streamed = await!(forward(
+            store
+                .internals_get_store()
+                .stream_after::<TestEvents>(&query, 0)
+                .collect()
+        ))?;
+
+        assert_eq!(streamed.len(), 2500);
+
+        let arg = String::new();
+
+        let result: TestCounterEntity = await!(store.aggregate(&arg))?;
+
+        Ok(result)
+    })
+    .unwrap();
+
+    assert_eq!(result, TestCounterEntity { counter: 2500 });
+}
+
+#[test]
+fn memory_event_query() {
+    let _ = pretty_env_logger::try_init();
+
+    let events = run_async(async {
+        let adapter = MemoryStoreAdapter::new();
+
+        let first = Event::from_data(TestEvent { num: 1 });
+        let second = Event::from_data(TestEvent { num: 2 });
+        let other = Event::from_data(OtherEvent { num: 2 });
+
+        await!(adapter.save(&first))?;
+        await!(adapter.save(&second))?;
+        await!(adapter.save(&other))?;
+
+        let query = MemoryQuery::from(
+            EventQuery::new()
+                .with_event::<TestEvent>()
+                .with_field("num", 2),
+        );
+
+        let events: Vec<TestEvents> = await!(adapter.read(&query, None))?;
+
+        Ok(events)
+    })
+    .unwrap();
+
+    assert_eq!(events.len(), 1);
+
+    match &events[0] {
+        TestEvents::Inc(event) => assert_eq!(event.data.num, 2),
+    }
+
+    // The cache key doesn't depend on the order filters are added in
+    let first = EventQuery::new().with_namespace("a").with_namespace("b");
+    let second = EventQuery::new().with_namespace("b").with_namespace("a");
+
+    assert_eq!(first.unique_id(), second.unique_id());
+    assert_ne!(first.unique_id(), EventQuery::new().unique_id());
+}
+
+#[test]
+fn memory_typed_read_events_since() {
+    let _ = pretty_env_logger::try_init();
+
+    run_async(async {
+        let store = SubscribableStore::new(
+            MemoryStoreAdapter::new(),
+            MemoryCacheAdapter::new(),
+            MemoryEmitterAdapter::new(),
+        )?;
+
+        let since = chrono::Utc::now();
+
+        let first = Event::from_data(TestEvent { num: 1 });
+        let other = Event::from_data(OtherEvent { num: 2 });
+        let third = Event::from_data(TestEvent { num: 3 });
+
+        await!(store.save(&first))?;
+        await!(store.save(&other))?;
+        await!(store.save(&third))?;
+
+        let inner = store.internals_get_store();
+
+        let typed = await!(inner.read_events_since::<TestEvent>(since))?;
+        let any = await!(inner.read_any_events_since::<TestEvents>(since))?;
+        let raw = await!(inner.read_raw_events_since("memory_test", "OtherEvent", since))?;
+
+        assert_eq!(
+            typed
+                .iter()
+                .map(|event| event.data.num)
+                .collect::<Vec<i32>>(),
+            vec![1, 3]
+        );
+        assert_eq!(any.len(), 2);
+        assert_eq!(raw.len(), 1);
+        assert_eq!(raw[0]["data"]["num"], 2);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+/// A `TestEvent` saved with data of the wrong type
+mod malformed {
+    use event_store_derive::*;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(EventData, Debug, Clone)]
+    #[event_store(namespace = "some_namespace")]
+    pub struct TestEvent {
+        pub num: String,
+    }
+}
+
+#[test]
+fn memory_typed_read_events_since_decode_policy() {
+    let _ = pretty_env_logger::try_init();
+
+    let (decoded, failures) = run_async(async {
+        let report = DecodeReport::new();
+
+        let store = SubscribableStore::new(
+            MemoryStoreAdapter::new().with_decode_policy(DecodePolicy::Collect(report.clone())),
+            MemoryCacheAdapter::new(),
+            MemoryEmitterAdapter::new(),
+        )?;
+
+        let since = chrono::Utc::now();
+
+        let valid = Event::from_data(TestEvent { num: 1 });
+        let malformed = Event::from_data(malformed::TestEvent { num: "one".into() });
+
+        await!(store.save(&valid))?;
+        await!(store.save(&malformed))?;
+
+        let inner = store.internals_get_store();
+
+        let events = await!(inner.read_events_since::<TestEvent>(since))?;
+
+        Ok((events.len(), report.take()))
+    })
+    .unwrap();
+
+    assert_eq!(decoded, 1);
+    assert_eq!(failures.len(), 1);
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct UserSubject {
+    user_id: String,
+    org_id: String,
+}
+
+#[test]
+fn memory_typed_subject() {
+    let _ = pretty_env_logger::try_init();
+
+    let events = run_async(async {
+        let store = SubscribableStore::new(
+            MemoryStoreAdapter::new(),
+            MemoryCacheAdapter::new(),
+            MemoryEmitterAdapter::new(),
+        )?;
+
+        let subject = |user_id: &str| UserSubject {
+            user_id: user_id.into(),
+            org_id: "org-1".into(),
+        };
+
+        let alice = Event::from_data(TestEvent { num: 1 }).with_subject(subject("alice"));
+        let bob = Event::from_data(TestEvent { num: 2 }).with_subject(subject("bob"));
+        let anonymous = Event::from_data(TestEvent { num: 3 });
+
+        await!(store.save(&alice))?;
+        await!(store.save(&bob))?;
+        await!(store.save(&anonymous))?;
+
+        let query = MemoryQuery::from(
+            EventQuery::for_events::<TestEvents>().with_subject_field("user_id", "bob"),
+        );
+
+        let events = await!(store
+            .internals_get_store()
+            .read_after::<TestEvents>(&query, 0))?;
+
+        Ok(events)
+    })
+    .unwrap();
+
+    assert_eq!(events.len(), 1);
+
+    match &events[0] {
+        TestEvents::Inc(event) => {
+            assert_eq!(event.data.num, 2);
+            assert_eq!(
+                event.context.subject,
+                Some(serde_json::json!({ "user_id": "bob", "org_id": "org-1" }))
+            );
+        }
+    }
+}
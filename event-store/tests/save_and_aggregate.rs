@@ -1,22 +1,21 @@
 #![feature(await_macro, async_await)]
 #![feature(arbitrary_self_types)]
 
-use event_store::adapters::{AmqpEmitterAdapter, PgCacheAdapter, PgStoreAdapter};
-use event_store::internals::{backward, forward, test_helpers::*};
+use event_store::adapters::{
+    AmqpEmitterAdapter, PgCacheAdapter, PgCacheTables, PgQuery, PgStoreAdapter, PgStoreTables,
+};
+use event_store::internals::{forward, test_helpers::*};
 use event_store::prelude::*;
 use event_store::SubscribableStore;
-use futures::future::Future;
 use log::trace;
-use std::io;
 use std::time::{Duration, Instant};
-use tokio::runtime::Runtime;
 use tokio::timer::Delay;
 
 #[test]
 fn save_and_aggregate() {
-    pretty_env_logger::init();
+    let _ = pretty_env_logger::try_init();
 
-    let fut = backward(async {
+    let (uncached_result, cached_result) = run_async(async {
         let test_event = Event::from_data(TestEvent { num: 100 });
         let test_event_2 = Event::from_data(TestEvent { num: 200 });
 
@@ -51,11 +50,96 @@ fn save_and_aggregate() {
 
         Ok((uncached_result, cached_result))
     })
-    // Required so Rust can figure out what type `E` is
-    .map_err(|e: io::Error| e);
-
-    let (uncached_result, cached_result) = Runtime::new().unwrap().block_on(fut).unwrap();
+    .unwrap();
 
     assert_eq!(uncached_result, TestCounterEntity { counter: 300i32 });
     assert_eq!(uncached_result, cached_result);
 }
+
+#[test]
+fn pg_migrations_applied_once() {
+    let _ = pretty_env_logger::try_init();
+
+    let mut counts = run_async(async {
+        let pool = pg_create_random_db(Some("migrations"));
+
+        await!(PgStoreAdapter::new(pool.clone()))?;
+        await!(PgCacheAdapter::new(pool.clone()))?;
+
+        // Creating adapters for an up to date database applies nothing
+        await!(PgStoreAdapter::new(pool.clone()))?;
+        await!(PgCacheAdapter::new(pool.clone()))?;
+
+        let rows = pool.get().unwrap().query(
+            "select migration_set, count(*), count(distinct version) from schema_migrations group by migration_set",
+            &[],
+        )?;
+
+        Ok(rows
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect::<Vec<(String, i64, i64)>>())
+    }).unwrap();
+
+    counts.sort();
+
+    assert_eq!(
+        counts,
+        vec![("aggregate_cache".into(), 4, 4), ("events".into(), 5, 5)]
+    );
+}
+
+#[test]
+fn pg_custom_schema_and_tables() {
+    let _ = pretty_env_logger::try_init();
+
+    let (events, cached, tables) = run_async(async {
+        let pool = pg_create_random_db(Some("custom_tables"));
+
+        let tables = PgStoreTables::new()
+            .with_schema("tenant_a")
+            .with_events_table("tenant_events")
+            .with_outbox_table("tenant_outbox");
+
+        let store = await!(PgStoreAdapter::new_with_tables(pool.clone(), tables))?;
+        let cache = await!(PgCacheAdapter::new_with_tables(
+            pool.clone(),
+            PgCacheTables::new()
+                .with_schema("tenant_a")
+                .with_cache_table("tenant_cache")
+        ))?;
+
+        let event = Event::from_data(TestEvent { num: 42 });
+
+        await!(store.save(&event))?;
+        await!(cache.save("_test", &TestCounterEntity { counter: 42 }, 1))?;
+
+        let query = PgQuery::from(EventQuery::for_events::<TestEvents>());
+        let events = await!(store.read::<TestEvents>(&query, None))?;
+        let cached = await!(cache.read::<TestCounterEntity>("_test"))?;
+
+        let tables = pool.get().unwrap().query(
+            "select table_name::text from information_schema.tables where table_schema = 'tenant_a' order by table_name",
+            &[],
+        )?;
+
+        Ok((
+            events.len(),
+            cached.map(|(entity, _)| entity),
+            tables.iter().map(|row| row.get(0)).collect::<Vec<String>>(),
+        ))
+    }).unwrap();
+
+    assert_eq!(events, 1);
+    assert_eq!(cached, Some(TestCounterEntity { counter: 42 }));
+    assert_eq!(
+        tables,
+        vec![
+            String::from("aggregate_snapshots"),
+            String::from("schema_migrations"),
+            String::from("tenant_cache"),
+            String::from("tenant_events"),
+            String::from("tenant_outbox"),
+        ]
+    );
+}
@@ -0,0 +1,268 @@
+#![feature(await_macro, async_await)]
+#![feature(arbitrary_self_types)]
+
+use event_store::adapters::{
+    MemoryEmitterAdapter, SaveStatus, SqliteCacheAdapter, SqliteQuery, SqliteStoreAdapter,
+};
+use event_store::internals::test_helpers::*;
+use event_store::prelude::*;
+use event_store::SubscribableStore;
+use log::trace;
+use rusqlite::types::ToSql;
+use rusqlite::Connection;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn sqlite_save_and_aggregate() {
+    let _ = pretty_env_logger::try_init();
+
+    let (uncached_result, cached_result) = run_async(async {
+        let test_event = Event::from_data(TestEvent { num: 100 });
+        let test_event_2 = Event::from_data(TestEvent { num: 200 });
+
+        trace!("SQLite save and aggregate test");
+
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory()?));
+
+        let store_adapter = await!(SqliteStoreAdapter::new(conn.clone()))?;
+
+        let store = SubscribableStore::new(
+            store_adapter.clone(),
+            await!(SqliteCacheAdapter::new(conn.clone()))?,
+            MemoryEmitterAdapter::new(),
+        )?;
+
+        await!(store.save(&test_event))?;
+        await!(store.save(&test_event_2))?;
+
+        let duplicate = await!(store_adapter.save(&test_event))?;
+
+        assert!(match duplicate {
+            SaveStatus::Duplicate => true,
+            _ => false,
+        });
+
+        let arg = &String::new();
+
+        let uncached_result: TestCounterEntity = await!(store.aggregate(arg))?;
+
+        let cached_result: TestCounterEntity = await!(store.aggregate(arg))?;
+
+        Ok((uncached_result, cached_result))
+    })
+    .unwrap();
+
+    assert_eq!(uncached_result, TestCounterEntity { counter: 300i32 });
+    assert_eq!(uncached_result, cached_result);
+}
+
+#[test]
+fn sqlite_read_events_since() {
+    let _ = pretty_env_logger::try_init();
+
+    let (events, new_event_id) = run_async(async {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory()?));
+
+        let adapter = await!(SqliteStoreAdapter::new(conn))?;
+
+        let old_event = Event::from_data(TestEvent { num: 1 });
+        let mut new_event = Event::from_data(TestEvent { num: 2 });
+        new_event.context.time = old_event.context.time + chrono::Duration::seconds(10);
+
+        await!(adapter.save(&old_event))?;
+        await!(adapter.save(&new_event))?;
+
+        let since = old_event.context.time + chrono::Duration::seconds(5);
+
+        let events = await!(adapter.read_events_since("some_namespace", "TestEvent", since))?;
+
+        Ok((events, new_event.id))
+    })
+    .unwrap();
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["id"], new_event_id.to_string());
+}
+
+#[test]
+fn sqlite_save_expecting() {
+    let _ = pretty_env_logger::try_init();
+
+    let (events, version) = run_async(async {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory()?));
+
+        let adapter = await!(SqliteStoreAdapter::new(conn))?;
+
+        let stream_id = "counter-1";
+
+        let first = Event::from_data(TestEvent { num: 1 }).with_stream_id(stream_id);
+        let stale = Event::from_data(TestEvent { num: 2 }).with_stream_id(stream_id);
+
+        await!(adapter.save_expecting(&first, 0))?;
+
+        let conflict = await!(adapter.save_expecting(&stale, 0));
+
+        assert!(match conflict {
+            Err(Error::Concurrency(_)) => true,
+            _ => false,
+        });
+
+        let query = SqliteQuery::new("select * from events", Vec::new());
+
+        let events: Vec<TestEvents> = await!(adapter.read(&query, None))?;
+
+        Ok((events, await!(adapter.stream_version(stream_id))?))
+    })
+    .unwrap();
+
+    assert_eq!(version, 1);
+    assert_eq!(events.len(), 1);
+
+    match &events[0] {
+        TestEvents::Inc(event) => {
+            assert_eq!(event.stream_id, Some("counter-1".into()));
+            assert_eq!(event.sequence, Some(1));
+        }
+    }
+}
+
+#[test]
+fn sqlite_save_expecting_checks_version() {
+    let _ = pretty_env_logger::try_init();
+
+    let version = run_async(async {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory()?));
+
+        let adapter = await!(SqliteStoreAdapter::new(conn))?;
+
+        let stream_id = "counter-1";
+
+        let first = Event::from_data(TestEvent { num: 1 }).with_stream_id(stream_id);
+        let ahead = Event::from_data(TestEvent { num: 2 }).with_stream_id(stream_id);
+        let mut third = Event::from_data(TestEvent { num: 3 }).with_stream_id(stream_id);
+        let stale = Event::from_data(TestEvent { num: 4 }).with_stream_id(stream_id);
+
+        await!(adapter.save_expecting(&first, 0))?;
+
+        // The stream is at version 1, not 2
+        let ahead_result = await!(adapter.save_expecting(&ahead, 2));
+
+        assert!(match ahead_result {
+            Err(Error::Concurrency(_)) => true,
+            _ => false,
+        });
+
+        // Leave a gap at sequence number 2
+        third.sequence = Some(3);
+
+        await!(adapter.save(&third))?;
+
+        // A writer that read version 1 must not fill the gap
+        let stale_result = await!(adapter.save_expecting(&stale, 1));
+
+        assert!(match stale_result {
+            Err(Error::Concurrency(_)) => true,
+            _ => false,
+        });
+
+        await!(adapter.stream_version(stream_id))
+    })
+    .unwrap();
+
+    assert_eq!(version, 3);
+}
+
+#[test]
+fn sqlite_event_query() {
+    let _ = pretty_env_logger::try_init();
+
+    let events = run_async(async {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory()?));
+
+        let adapter = await!(SqliteStoreAdapter::new(conn))?;
+
+        let first = Event::from_data(TestEvent { num: 1 }).with_stream_id("a");
+        let second = Event::from_data(TestEvent { num: 2 }).with_stream_id("a");
+        let other = Event::from_data(TestEvent { num: 2 }).with_stream_id("b");
+
+        await!(adapter.save(&first))?;
+        await!(adapter.save(&second))?;
+        await!(adapter.save(&other))?;
+
+        let query = EventQuery::for_events::<TestEvents>()
+            .with_stream_id("a")
+            .with_field("num", 2);
+
+        let query = SqliteQuery::from(query);
+
+        let events: Vec<TestEvents> = await!(adapter.read(&query, None))?;
+
+        Ok(events)
+    })
+    .unwrap();
+
+    assert_eq!(events.len(), 1);
+
+    match &events[0] {
+        TestEvents::Inc(event) => {
+            assert_eq!(event.stream_id, Some("a".into()));
+            assert_eq!(event.data.num, 2);
+        }
+    }
+}
+
+#[test]
+fn sqlite_namespace_and_type_columns() {
+    let _ = pretty_env_logger::try_init();
+
+    let events = run_async(async {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory()?));
+
+        let adapter = await!(SqliteStoreAdapter::new(conn))?;
+
+        let event = Event::from_data(TestEvent { num: 1 });
+
+        await!(adapter.save(&event))?;
+
+        let args: Vec<Box<ToSql + Send + Sync>> = vec![
+            Box::new(String::from("some_namespace")),
+            Box::new(String::from("TestEvent")),
+        ];
+
+        let query = SqliteQuery::new(
+            "select * from events where event_namespace = ?1 and event_type = ?2",
+            args,
+        );
+
+        let events: Vec<TestEvents> = await!(adapter.read(&query, None))?;
+
+        Ok(events)
+    })
+    .unwrap();
+
+    assert_eq!(events.len(), 1);
+}
+
+#[test]
+fn sqlite_query_error() {
+    let _ = pretty_env_logger::try_init();
+
+    let result = run_async(async {
+        let conn = Arc::new(Mutex::new(Connection::open_in_memory()?));
+
+        let adapter = await!(SqliteStoreAdapter::new(conn))?;
+
+        let query = SqliteQuery::new("select * from missing_table", Vec::new());
+
+        let result: Result<Vec<TestEvents>, Error> = await!(adapter.read(&query, None));
+
+        Ok(result)
+    })
+    .unwrap();
+
+    // The database was reached, so this isn't a connection error
+    assert!(match result {
+        Err(Error::Database(_)) => true,
+        _ => false,
+    });
+}
@@ -0,0 +1,106 @@
+#![feature(await_macro, async_await)]
+#![feature(arbitrary_self_types)]
+
+use event_store::adapters::{
+    EventRecord, MemoryCacheAdapter, MemoryEmitterAdapter, MemoryStoreAdapter,
+};
+use event_store::internals::test_helpers::*;
+use event_store::prelude::*;
+use event_store::{SubscribableStore, Upcaster};
+use event_store_derive::*;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+#[derive(EventData, Debug, Clone)]
+#[event_store(namespace = "memory_test")]
+#[event_store(version = 2)]
+#[event_store(upcasters = "VERSIONED_EVENT_UPCASTERS")]
+struct VersionedEvent {
+    pub total: i32,
+    pub unit: String,
+}
+
+const VERSIONED_EVENT_UPCASTERS: &[Upcaster] = &[Upcaster {
+    from_version: 1,
+    upcast: upcast_versioned_event_v1,
+}];
+
+/// Version 1 called the total `count` and had no unit
+fn upcast_versioned_event_v1(mut payload: JsonValue) -> Result<JsonValue, String> {
+    payload["total"] = payload["count"].take();
+    payload["unit"] = json!("items");
+
+    Ok(payload)
+}
+
+#[test]
+fn upcast_versioned_events() {
+    let _ = pretty_env_logger::try_init();
+
+    let events = run_async(async {
+        let adapter = MemoryStoreAdapter::new();
+
+        let store = SubscribableStore::new(
+            adapter.clone(),
+            MemoryCacheAdapter::new(),
+            MemoryEmitterAdapter::new(),
+        )?;
+
+        let since = chrono::Utc::now();
+
+        // Save an event in its version 1 shape, as it would have been saved before the upgrade
+        let mut old = EventRecord::from_event(&Event::from_data(VersionedEvent {
+            total: 0,
+            unit: String::new(),
+        }))?;
+
+        old.data = json!({
+            "event_namespace": "memory_test",
+            "event_type": "VersionedEvent",
+            "count": 5,
+        });
+
+        let records = [old];
+        let new = Event::from_data(VersionedEvent {
+            total: 7,
+            unit: "boxes".into(),
+        });
+
+        await!(adapter.save_all(&records))?;
+        await!(store.save(&new))?;
+
+        let events = await!(store
+            .internals_get_store()
+            .read_events_since::<VersionedEvent>(since))?;
+
+        Ok(events
+            .into_iter()
+            .map(|event| (event.data.total, event.data.unit))
+            .collect::<Vec<(i32, String)>>())
+    })
+    .unwrap();
+
+    assert_eq!(
+        events,
+        vec![(5, String::from("items")), (7, String::from("boxes"))]
+    );
+
+    let serialized = serde_json::to_value(VersionedEvent {
+        total: 1,
+        unit: "items".into(),
+    })
+    .unwrap();
+
+    assert_eq!(serialized["event_version"], 2);
+
+    // Payloads from a newer version of the event can't be read
+    let newer = serde_json::from_value::<VersionedEvent>(json!({
+        "event_namespace": "memory_test",
+        "event_type": "VersionedEvent",
+        "event_version": 3,
+        "total": 1,
+        "unit": "items",
+    }));
+
+    assert!(newer.is_err());
+}
@@ -10,7 +10,7 @@ use chrono::prelude::*;
 use event_store::{
     adapters::{AmqpEmitterAdapter, PgCacheAdapter, PgStoreAdapter},
     internals::backward,
-    EventContext, SubscribableStore,
+    Error, EventContext, SubscribableStore,
 };
 use gtk::prelude::*;
 use log::{debug, info, trace};
@@ -96,7 +96,7 @@ fn connect(db: &String) -> Result<Pool<PostgresConnectionManager>, io::Error> {
 
 async fn create_store(
     pool: &Pool<PostgresConnectionManager>,
-) -> Result<SubscribableStore, io::Error> {
+) -> Result<SubscribableStore<PgStoreAdapter, PgCacheAdapter, AmqpEmitterAdapter>, Error> {
     let store_adapter = await!(PgStoreAdapter::new(pool.clone()))?;
     let cache_adapter = await!(PgCacheAdapter::new(pool.clone()))?;
     let emitter_adapter = await!(AmqpEmitterAdapter::new(
@@ -108,14 +108,17 @@ async fn create_store(
     SubscribableStore::new(store_adapter, cache_adapter, emitter_adapter)
 }
 
-async fn do_search(query: String, store: &SubscribableStore) -> Result<Vec<AnyEvent>, io::Error> {
+async fn do_search(
+    query: String,
+    store: &SubscribableStore<PgStoreAdapter, PgCacheAdapter, AmqpEmitterAdapter>,
+) -> Result<Vec<AnyEvent>, Error> {
     let parts: Vec<&str> = query.split('.').collect();
 
     let forever = Utc.ymd(1970, 1, 1).and_hms(0, 0, 0);
 
     await!(store
         .internals_get_store()
-        .read_events_since(parts[0], parts[1], forever))
+        .read_raw_events_since(parts[0], parts[1], forever))
     .map(|result| {
         result
             .into_iter()